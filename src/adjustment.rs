@@ -0,0 +1,181 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer};
+
+/// A change to a continuous VCP value such as brightness, written as an
+/// absolute value (`40`), a percentage of the monitor's maximum (`40%`), or
+/// a step relative to the current value (`+10`, `-5%`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Adjustment {
+    amount: i64,
+    percent: bool,
+    relative: bool,
+}
+
+impl Adjustment {
+    /// Returns the new value given the current and maximum values reported by
+    /// the monitor, clamped to `0..=max`.
+    pub fn apply(&self, current: u32, max: u32) -> u32 {
+        let max = i64::from(max);
+        let amount = if self.percent {
+            (self.amount * max + self.amount.signum() * 50) / 100
+        } else {
+            self.amount
+        };
+
+        let target = if self.relative {
+            i64::from(current) + amount
+        } else {
+            amount
+        };
+
+        target.max(0).min(max) as u32
+    }
+}
+
+impl FromStr for Adjustment {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (relative, sign, rest) = match s.as_bytes().first() {
+            Some(b'+') => (true, 1, &s[1..]),
+            Some(b'-') => (true, -1, &s[1..]),
+            _ => (false, 1, s),
+        };
+
+        let (percent, digits) = match rest.strip_suffix('%') {
+            Some(digits) => (true, digits),
+            None => (false, rest),
+        };
+
+        let amount: u32 = digits
+            .parse()
+            .map_err(|_| format!("invalid value: {}", s))?;
+
+        Ok(Adjustment {
+            amount: sign * i64::from(amount),
+            percent,
+            relative,
+        })
+    }
+}
+
+impl fmt::Display for Adjustment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.relative && self.amount >= 0 {
+            write!(f, "+")?;
+        }
+        write!(f, "{}", self.amount)?;
+        if self.percent {
+            write!(f, "%")?;
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes from either an absolute number, or a string in any of the
+/// forms accepted by `from_str`.
+impl<'de> Deserialize<'de> for Adjustment {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u32),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(amount) => Ok(Adjustment {
+                amount: i64::from(amount),
+                percent: false,
+                relative: false,
+            }),
+            Raw::Text(s) => s.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(adjustment: &str, current: u32, max: u32) -> u32 {
+        adjustment
+            .parse::<Adjustment>()
+            .unwrap()
+            .apply(current, max)
+    }
+
+    #[test]
+    fn applies_absolute_values() {
+        assert_eq!(apply("40", 10, 100), 40);
+        assert_eq!(apply("0", 10, 100), 0);
+        assert_eq!(apply("150", 10, 100), 100);
+    }
+
+    #[test]
+    fn applies_relative_values() {
+        assert_eq!(apply("+10", 50, 100), 60);
+        assert_eq!(apply("-10", 50, 100), 40);
+        assert_eq!(apply("+20", 90, 100), 100);
+        assert_eq!(apply("-20", 10, 100), 0);
+        // A monitor reporting a value above its max is brought back in range.
+        assert_eq!(apply("+0", 120, 100), 100);
+    }
+
+    #[test]
+    fn rounds_percentages_to_the_nearest_value() {
+        assert_eq!(apply("50%", 0, 80), 40);
+        assert_eq!(apply("33%", 0, 80), 26);
+        assert_eq!(apply("35%", 0, 10), 4);
+        assert_eq!(apply("100%", 0, 80), 80);
+        assert_eq!(apply("150%", 0, 80), 80);
+        // Halves round away from zero either way, so a step of 5% of 10 is
+        // a whole step up or down rather than none.
+        assert_eq!(apply("+5%", 5, 10), 6);
+        assert_eq!(apply("-5%", 5, 10), 4);
+        assert_eq!(apply("-4%", 5, 10), 5);
+        assert_eq!(apply("-50%", 10, 80), 0);
+    }
+
+    #[test]
+    fn parses_and_displays_adjustments() {
+        for s in &["40", "40%", "+10", "-5", "+5%", "-5%", "0"] {
+            assert_eq!(s.parse::<Adjustment>().unwrap().to_string(), *s);
+        }
+
+        for s in &[
+            "",
+            "+",
+            "-",
+            "%",
+            "+%",
+            "abc",
+            "1.5",
+            "--5",
+            "+-5",
+            "5%%",
+            "4294967296",
+        ] {
+            assert!(
+                s.parse::<Adjustment>().is_err(),
+                "{:?} should be invalid",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn deserializes_numbers_and_strings() {
+        let absolute: Adjustment = serde_json::from_str("40").unwrap();
+        assert_eq!(absolute, "40".parse().unwrap());
+
+        let relative: Adjustment = serde_json::from_str("\"-5%\"").unwrap();
+        assert_eq!(relative, "-5%".parse().unwrap());
+
+        assert!(serde_json::from_str::<Adjustment>("-5").is_err());
+        assert!(serde_json::from_str::<Adjustment>("\"up\"").is_err());
+    }
+}
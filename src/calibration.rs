@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+
+use serde::Deserialize;
+
+use crate::adjustment::Adjustment;
+use crate::monitors::{self, Monitor, VcpValue};
+use crate::selector::{Aliases, Selector};
+
+/// The highest brightness level, which curves map onto the monitor's own
+/// values.
+pub const MAX_LEVEL: u32 = 100;
+
+/// Maps brightness levels from 0 to 100 onto the values a particular monitor
+/// needs to look equally bright, given in the config as points to
+/// interpolate between, e.g. `[[0, 0], [50, 30], [100, 80]]`. Levels beyond
+/// the first or last point are clamped to it.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "Vec<(u32, u32)>")]
+pub struct Curve {
+    points: Vec<(u32, u32)>,
+}
+
+/// The brightness curves that apply to the connected monitors, keyed by
+/// monitor id.
+#[derive(Debug, Default)]
+pub struct Calibration {
+    curves: HashMap<String, Curve>,
+}
+
+impl Curve {
+    /// The value to write for a level.
+    pub fn value(&self, level: u32) -> u32 {
+        let level = level.min(MAX_LEVEL);
+        interpolate(&self.points, level)
+    }
+
+    /// The level that a value corresponds to, which is approximate if the
+    /// value falls between points. Where the curve is flat, it's the highest
+    /// level with that value.
+    pub fn level(&self, value: u32) -> u32 {
+        let inverse: Vec<_> = self.points.iter().map(|&(l, v)| (v, l)).collect();
+        interpolate(&inverse, value)
+    }
+}
+
+impl TryFrom<Vec<(u32, u32)>> for Curve {
+    type Error = String;
+
+    fn try_from(points: Vec<(u32, u32)>) -> Result<Self, Self::Error> {
+        if points.is_empty() {
+            return Err("calibration curve has no points".to_owned());
+        }
+
+        if let Some(&(level, _)) = points.iter().find(|(level, _)| *level > MAX_LEVEL) {
+            return Err(format!(
+                "calibration level {} is above {}",
+                level, MAX_LEVEL
+            ));
+        }
+
+        // Increasing values are what make the curve invertible, so that
+        // relative changes can be made in levels too.
+        for pair in points.windows(2) {
+            let ((l1, v1), (l2, v2)) = (pair[0], pair[1]);
+            if l2 <= l1 || v2 < v1 {
+                return Err(format!(
+                    "calibration points must increase: [{}, {}] is followed by [{}, {}]",
+                    l1, v1, l2, v2
+                ));
+            }
+        }
+
+        Ok(Curve { points })
+    }
+}
+
+impl Calibration {
+    /// Works out which monitors the curves in the config apply to, keyed by
+    /// selector. Where several match the same monitor, the first one wins.
+    /// Selectors that don't match any of the monitors are skipped, since the
+    /// monitors they are for may just not be connected.
+    pub fn resolve<'a>(
+        entries: impl IntoIterator<Item = (&'a String, &'a Curve)>,
+        monitors: &[Monitor],
+        aliases: &Aliases,
+    ) -> Result<Calibration, Box<dyn Error>> {
+        let mut curves = HashMap::new();
+
+        for (selector, curve) in entries {
+            let parsed: Selector = selector
+                .parse()
+                .map_err(|e| format!("invalid calibration selector {}: {}", selector, e))?;
+
+            let selected = match parsed.select(monitors, aliases) {
+                Ok(selected) => selected,
+                Err(e) => {
+                    log::debug!("skipping calibration for {}: {}", selector, e);
+                    continue;
+                }
+            };
+
+            for monitor in selected {
+                curves
+                    .entry(monitor.id().to_owned())
+                    .or_insert_with(|| curve.clone());
+            }
+        }
+
+        Ok(Calibration { curves })
+    }
+
+    /// Returns the brightness curve for the monitor, if it has one.
+    pub fn curve(&self, monitor: &Monitor) -> Option<&Curve> {
+        self.curves.get(monitor.id())
+    }
+
+    /// Applies the adjustment to a VCP value. Brightness adjustments for
+    /// calibrated monitors are made in levels and mapped through the curve,
+    /// so that the same level looks the same on every monitor. Everything
+    /// else is applied to the monitor's value directly.
+    pub fn adjust(
+        &self,
+        monitor: &Monitor,
+        code: u8,
+        adjustment: Adjustment,
+        value: &VcpValue,
+    ) -> u32 {
+        match self.curve(monitor) {
+            Some(curve) if code == monitors::VCP_BRIGHTNESS => {
+                let level = adjustment.apply(curve.level(value.current), MAX_LEVEL);
+                curve.value(level).min(value.max)
+            }
+            _ => adjustment.apply(value.current, value.max),
+        }
+    }
+}
+
+/// Linearly interpolates between sorted points, rounding to the nearest
+/// whole value and clamping to the ends. Where several points share an `x`,
+/// the last of them wins, wherever they are in the curve.
+fn interpolate(points: &[(u32, u32)], x: u32) -> u32 {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    if x < first.0 {
+        return first.1;
+    }
+    if x >= last.0 {
+        return last.1;
+    }
+
+    let i = points.iter().position(|&(px, _)| px > x).unwrap();
+    let ((x1, y1), (x2, y2)) = (points[i - 1], points[i]);
+    let (x, x1, y1, x2, y2) = (
+        i64::from(x),
+        i64::from(x1),
+        i64::from(y1),
+        i64::from(x2),
+        i64::from(y2),
+    );
+
+    let dx = x2 - x1;
+    (y1 + ((y2 - y1) * (x - x1) * 2 + dx) / (2 * dx)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::monitors::{DisplayBackend, SimBackend, VcpType, VCP_BRIGHTNESS, VCP_CONTRAST};
+
+    fn curve(points: &[(u32, u32)]) -> Curve {
+        Curve::try_from(points.to_vec()).unwrap()
+    }
+
+    fn value(current: u32, max: u32) -> VcpValue {
+        VcpValue {
+            current,
+            max,
+            kind: VcpType::SetParameter,
+        }
+    }
+
+    fn monitors() -> Vec<Monitor> {
+        let path = monitors::write_fixture(
+            "calibration",
+            r#"
+            [[monitors]]
+            id = 1
+            name = "Dell"
+
+            [[monitors]]
+            id = 2
+            name = "LG"
+            "#,
+        );
+        let backend: Arc<dyn DisplayBackend> = Arc::new(SimBackend::from_file(path).unwrap());
+        monitors::get_monitors(&backend).unwrap()
+    }
+
+    fn resolve(
+        curves: &[(String, Curve)],
+        monitors: &[Monitor],
+    ) -> Result<Calibration, Box<dyn Error>> {
+        let entries = curves.iter().map(|(selector, curve)| (selector, curve));
+        Calibration::resolve(entries, monitors, &Aliases::new())
+    }
+
+    #[test]
+    fn interpolates_between_points() {
+        let points = [(10, 0), (20, 10), (40, 11), (50, 100)];
+
+        assert_eq!(interpolate(&points, 10), 0);
+        assert_eq!(interpolate(&points, 15), 5);
+        assert_eq!(interpolate(&points, 20), 10);
+        assert_eq!(interpolate(&points, 45), 56);
+        assert_eq!(interpolate(&points, 50), 100);
+    }
+
+    #[test]
+    fn rounds_to_the_nearest_value() {
+        // A third, two thirds and a half of the way along.
+        assert_eq!(interpolate(&[(0, 0), (3, 1)], 1), 0);
+        assert_eq!(interpolate(&[(0, 0), (3, 1)], 2), 1);
+        assert_eq!(interpolate(&[(0, 0), (2, 1)], 1), 1);
+        assert_eq!(interpolate(&[(0, 10), (2, 11)], 1), 11);
+    }
+
+    #[test]
+    fn clamps_to_the_ends() {
+        let points = [(10, 5), (20, 15)];
+
+        assert_eq!(interpolate(&points, 0), 5);
+        assert_eq!(interpolate(&points, 30), 15);
+        assert_eq!(interpolate(&[(50, 7)], 0), 7);
+        assert_eq!(interpolate(&[(50, 7)], 100), 7);
+    }
+
+    #[test]
+    fn maps_levels_to_values_and_back() {
+        let curve = curve(&[(10, 5), (50, 30), (100, 80)]);
+
+        assert_eq!(curve.value(0), 5);
+        assert_eq!(curve.value(30), 18);
+        assert_eq!(curve.value(50), 30);
+        assert_eq!(curve.value(150), 80);
+
+        assert_eq!(curve.level(0), 10);
+        assert_eq!(curve.level(18), 31);
+        assert_eq!(curve.level(30), 50);
+        assert_eq!(curve.level(100), 100);
+    }
+
+    #[test]
+    fn finds_levels_on_flat_segments() {
+        // Levels 50 to 60 all write 30, so reading back 30 gives the top of
+        // the flat segment, the same as at either end of the curve.
+        let curve = curve(&[(0, 0), (50, 30), (60, 30), (100, 80)]);
+
+        assert_eq!(curve.value(55), 30);
+        assert_eq!(curve.level(29), 48);
+        assert_eq!(curve.level(30), 60);
+        assert_eq!(curve.level(31), 61);
+
+        let start = self::curve(&[(0, 20), (50, 20), (100, 80)]);
+        assert_eq!(start.level(10), 0);
+        assert_eq!(start.level(20), 50);
+
+        let flat = self::curve(&[(0, 20), (100, 20)]);
+        assert_eq!(flat.value(50), 20);
+        assert_eq!(flat.level(0), 0);
+        assert_eq!(flat.level(20), 100);
+        assert_eq!(flat.level(40), 100);
+    }
+
+    #[test]
+    fn rejects_invalid_curves() {
+        for points in &[
+            vec![],
+            vec![(0, 0), (101, 80)],
+            vec![(0, 0), (50, 30), (50, 40)],
+            vec![(50, 0), (0, 30)],
+            vec![(0, 30), (100, 20)],
+        ] {
+            assert!(
+                Curve::try_from(points.clone()).is_err(),
+                "{:?} should have been rejected",
+                points
+            );
+        }
+
+        let curve: Curve = serde_json::from_str("[[0, 0], [100, 80]]").unwrap();
+        assert_eq!(curve.value(50), 40);
+        assert!(serde_json::from_str::<Curve>("[[0, 50], [100, 40]]").is_err());
+    }
+
+    #[test]
+    fn adjusts_calibrated_brightness_in_levels() {
+        let monitors = monitors();
+        // Where several curves match a monitor, the first one wins.
+        let curves = vec![
+            ("1".to_owned(), curve(&[(0, 0), (100, 50)])),
+            ("Dell,LG".to_owned(), curve(&[(0, 0), (100, 100)])),
+            ("missing".to_owned(), curve(&[(0, 0), (100, 10)])),
+        ];
+        let calibration = resolve(&curves, &monitors).unwrap();
+
+        let (dell, lg) = (&monitors[0], &monitors[1]);
+        let adjust = |monitor, code, adjustment: &str, current| {
+            calibration.adjust(
+                monitor,
+                code,
+                adjustment.parse().unwrap(),
+                &value(current, 100),
+            )
+        };
+
+        // Level 50 on the Dell is 25, so ten levels up is 30.
+        assert_eq!(adjust(dell, VCP_BRIGHTNESS, "+10", 25), 30);
+        assert_eq!(adjust(dell, VCP_BRIGHTNESS, "100", 25), 50);
+        assert_eq!(adjust(lg, VCP_BRIGHTNESS, "+10", 25), 35);
+        // Only brightness is calibrated.
+        assert_eq!(adjust(dell, VCP_CONTRAST, "+10", 25), 35);
+    }
+
+    #[test]
+    fn keeps_calibrated_values_within_the_max() {
+        let monitors = monitors();
+        let curves = vec![("all".to_owned(), curve(&[(0, 0), (100, 200)]))];
+        let calibration = resolve(&curves, &monitors).unwrap();
+
+        let adjusted = calibration.adjust(
+            &monitors[0],
+            VCP_BRIGHTNESS,
+            "80".parse().unwrap(),
+            &value(10, 100),
+        );
+        assert_eq!(adjusted, 100);
+    }
+
+    #[test]
+    fn rejects_invalid_selectors() {
+        let curves = vec![("1,,2".to_owned(), curve(&[(0, 0), (100, 100)]))];
+        assert!(resolve(&curves, &monitors()).is_err());
+    }
+}
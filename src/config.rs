@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::calibration::{Calibration, Curve};
+use crate::monitors::Monitor;
+use crate::scene::Scene;
+use crate::selector::{Aliases, Matcher, Selector};
+
+/// Settings read from `config.toml`, e.g.
+///
+/// ```toml
+/// [monitors]
+/// left = { serial = "ABC123" }
+/// right = { id = "GSM-5B7F-XYZ" }
+///
+/// [groups]
+/// desk = ["left", "right"]
+///
+/// [[scenes.movie]]
+/// monitors = "left"
+/// power = "off"
+///
+/// [calibration]
+/// left = [[0, 0], [50, 30], [100, 80]]
+/// ```
+///
+/// The system wide file is read first, then the user's file, whose entries
+/// take precedence over any with the same name. The user's calibration curves
+/// are also tried before the system's.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Names for individual monitors.
+    pub monitors: BTreeMap<String, Matcher>,
+    /// Names for sets of monitors, given as a list of selectors.
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// Presets applied with `mona scene apply`.
+    pub scenes: BTreeMap<String, Scene>,
+    /// Brightness curves, keyed by the selector for the monitors they apply
+    /// to, in the order they appear in the file.
+    #[serde(deserialize_with = "in_file_order")]
+    pub calibration: Vec<(String, Curve)>,
+}
+
+impl Config {
+    /// Loads the config from `path` if given, otherwise from the default
+    /// locations. Missing files are only an error if `path` is given.
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn Error>> {
+        if let Some(path) = path {
+            return Config::read(path)?
+                .ok_or_else(|| format!("config file not found: {}", path.display()).into());
+        }
+
+        let mut config = Config::default();
+
+        for path in system_path().into_iter().chain(user_path()) {
+            if let Some(other) = Config::read(&path)? {
+                log::debug!("loaded config from {}", path.display());
+                config.merge(other);
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Option<Config>, Box<dyn Error>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("failed to read {}: {}", path.display(), e).into()),
+        };
+
+        toml::from_str(&contents)
+            .map(Some)
+            .map_err(|e| format!("invalid config file {}: {}", path.display(), e).into())
+    }
+
+    fn merge(&mut self, other: Config) {
+        self.monitors.extend(other.monitors);
+        self.groups.extend(other.groups);
+        self.scenes.extend(other.scenes);
+
+        let system = mem::replace(&mut self.calibration, other.calibration);
+        for (selector, curve) in system {
+            if !self.calibration.iter().any(|(s, _)| *s == selector) {
+                self.calibration.push((selector, curve));
+            }
+        }
+    }
+
+    pub fn scene(&self, name: &str) -> Result<&Scene, Box<dyn Error>> {
+        self.scenes
+            .get(name)
+            .ok_or_else(|| format!("no scene named {}", name).into())
+    }
+
+    /// Returns the brightness curves for the given monitors.
+    pub fn calibration(
+        &self,
+        monitors: &[Monitor],
+        aliases: &Aliases,
+    ) -> Result<Calibration, Box<dyn Error>> {
+        let entries = self
+            .calibration
+            .iter()
+            .map(|(selector, curve)| (selector, curve));
+        Calibration::resolve(entries, monitors, aliases)
+    }
+
+    /// Returns the monitor and group names as selector aliases.
+    pub fn aliases(&self) -> Result<Aliases, Box<dyn Error>> {
+        let mut aliases = Aliases::new();
+
+        for (name, matcher) in &self.monitors {
+            if *matcher == Matcher::default() {
+                return Err(format!("monitor alias {} doesn't match on anything", name).into());
+            }
+            aliases.insert(name.to_lowercase(), Selector::matching(matcher.clone()));
+        }
+
+        for (name, members) in &self.groups {
+            if aliases.contains_key(&name.to_lowercase()) {
+                return Err(format!("{} is both a monitor alias and a group", name).into());
+            }
+
+            let selector = members
+                .join(",")
+                .parse()
+                .map_err(|e| format!("invalid group {}: {}", name, e))?;
+
+            aliases.insert(name.to_lowercase(), selector);
+        }
+
+        Ok(aliases)
+    }
+}
+
+/// Reads a table as a list of its entries, keeping the order they were
+/// written in.
+fn in_file_order<'de, D, V>(deserializer: D) -> Result<Vec<(String, V)>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    struct EntriesVisitor<V>(PhantomData<V>);
+
+    impl<'de, V: Deserialize<'de>> Visitor<'de> for EntriesVisitor<V> {
+        type Value = Vec<(String, V)>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a table")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut entries = Vec::new();
+            while let Some(entry) = map.next_entry()? {
+                entries.push(entry);
+            }
+            Ok(entries)
+        }
+    }
+
+    deserializer.deserialize_map(EntriesVisitor(PhantomData))
+}
+
+/// `%APPDATA%\mona\config.toml` on Windows, or
+/// `$XDG_CONFIG_HOME/mona/config.toml` elsewhere.
+fn user_path() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+
+    dir.map(|dir| dir.join("mona").join("config.toml"))
+}
+
+/// Where mona keeps the data it saves, `%LOCALAPPDATA%\mona` on Windows or
+/// `$XDG_DATA_HOME/mona` elsewhere.
+pub fn data_dir() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+    };
+
+    dir.map(|dir| dir.join("mona"))
+}
+
+/// `%ProgramData%\mona\config.toml` on Windows, or `/etc/mona/config.toml`
+/// elsewhere.
+fn system_path() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("ProgramData").map(|dir| Path::new(&dir).join("mona").join("config.toml"))
+    } else {
+        Some(PathBuf::from("/etc/mona/config.toml"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::monitors::{self, DisplayBackend, SimBackend};
+
+    fn monitors() -> Vec<Monitor> {
+        let path = monitors::write_fixture(
+            "config",
+            r#"
+            [[monitors]]
+            id = 1
+            name = "Dell"
+
+            [[monitors]]
+            id = 2
+            name = "LG"
+            "#,
+        );
+        let backend: Arc<dyn DisplayBackend> = Arc::new(SimBackend::from_file(path).unwrap());
+        monitors::get_monitors(&backend).unwrap()
+    }
+
+    fn parse(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    /// The value each monitor's curve gives for level 100.
+    fn top_values(config: &Config) -> Vec<Option<u32>> {
+        let monitors = monitors();
+        let calibration = config.calibration(&monitors, &Aliases::new()).unwrap();
+        monitors
+            .iter()
+            .map(|monitor| calibration.curve(monitor).map(|curve| curve.value(100)))
+            .collect()
+    }
+
+    #[test]
+    fn uses_the_first_curve_in_the_file() {
+        // `*` sorts before `Dell`, but comes after it in the file.
+        let config = parse(
+            r#"
+            [calibration]
+            Dell = [[0, 0], [100, 80]]
+            "*" = [[0, 0], [100, 60]]
+            "#,
+        );
+        assert_eq!(top_values(&config), [Some(80), Some(60)]);
+
+        let config = parse(
+            r#"
+            [calibration]
+            "*" = [[0, 0], [100, 60]]
+            Dell = [[0, 0], [100, 80]]
+            "#,
+        );
+        assert_eq!(top_values(&config), [Some(60), Some(60)]);
+    }
+
+    #[test]
+    fn tries_user_curves_before_system_curves() {
+        let mut config = parse(
+            r#"
+            [calibration]
+            "*" = [[0, 0], [100, 60]]
+            LG = [[0, 0], [100, 90]]
+            "#,
+        );
+        config.merge(parse(
+            r#"
+            [calibration]
+            Dell = [[0, 0], [100, 80]]
+            "*" = [[0, 0], [100, 70]]
+            "#,
+        ));
+
+        assert_eq!(
+            config
+                .calibration
+                .iter()
+                .map(|(selector, _)| selector.as_str())
+                .collect::<Vec<_>>(),
+            ["Dell", "*", "LG"]
+        );
+        assert_eq!(top_values(&config), [Some(80), Some(70)]);
+    }
+}
@@ -1,23 +1,80 @@
-use std::slice::Iter;
+use std::sync::Arc;
 
-use crate::monitors::{self, Monitor};
+use crate::monitors::{self, DisplayBackend, Monitor, MonitorError};
 
-pub struct Db(Vec<Monitor>);
+pub struct Db {
+    backend: Arc<dyn DisplayBackend>,
+    monitors: Vec<Monitor>,
+}
 
 impl Db {
-    pub fn new() -> Db {
-        Db(monitors::get_monitors())
+    pub fn new(backend: Arc<dyn DisplayBackend>) -> Result<Db, MonitorError> {
+        let monitors = monitors::get_monitors(&backend)?;
+        Ok(Db { backend, monitors })
     }
 
-    pub fn iter(&self) -> Iter<Monitor> {
-        self.0.iter()
+    pub fn monitors(&self) -> &[Monitor] {
+        &self.monitors
     }
 
-    pub fn get(&self, id: i32) -> Option<&Monitor> {
-        self.0.iter().find(|m| m.id() == id)
+    pub fn refresh(&mut self) -> Result<(), MonitorError> {
+        self.monitors = monitors::get_monitors(&self.backend)?;
+        Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::monitors::{write_fixture, PowerMode, SimBackend};
+
+    const DELL: &str = r#"
+        [[monitors]]
+        id = 1
+        name = "Dell"
+        edid = { manufacturer = "DEL", product = 0xa0b8, serial = "ABC123" }
+    "#;
+
+    const LG: &str = r#"
+        [[monitors]]
+        id = 2
+        name = "LG"
+        edid = { manufacturer = "GSM", product = 0x5b09, serial = "XYZ789" }
+    "#;
+
+    #[test]
+    fn refresh_picks_up_plugged_monitors() {
+        let path = write_fixture("db", &format!("{}{}", DELL, LG));
+        let mut db = Db::new(Arc::new(SimBackend::from_file(&path).unwrap())).unwrap();
+
+        let ids: Vec<_> = db.monitors().iter().map(|m| m.id()).collect();
+        assert_eq!(ids, ["DEL-A0B8-ABC123", "GSM-5B09-XYZ789"]);
+
+        // Unplugging the first monitor moves the second one up in the
+        // enumeration order, but not its id.
+        fs::write(&path, LG).unwrap();
+        db.refresh().unwrap();
+
+        let monitors: Vec<_> = db.monitors().iter().map(|m| (m.index(), m.id())).collect();
+        assert_eq!(monitors, [(1, "GSM-5B09-XYZ789")]);
+
+        fs::write(&path, format!("{}{}", LG, DELL)).unwrap();
+        db.refresh().unwrap();
+
+        let ids: Vec<_> = db.monitors().iter().map(|m| m.id()).collect();
+        assert_eq!(ids, ["GSM-5B09-XYZ789", "DEL-A0B8-ABC123"]);
+    }
+
+    #[test]
+    fn monitors_keep_their_state_across_refresh() {
+        let path = write_fixture("db", DELL);
+        let mut db = Db::new(Arc::new(SimBackend::from_file(&path).unwrap())).unwrap();
+
+        db.monitors()[0].set_power_mode(PowerMode::Off).unwrap();
+        db.refresh().unwrap();
 
-    pub fn refresh(&mut self) {
-        self.0 = monitors::get_monitors();
+        assert_eq!(db.monitors()[0].power_mode().unwrap(), PowerMode::Off);
     }
 }
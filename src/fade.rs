@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::monitors::{self, Monitor, MonitorError, PowerMode};
+
+/// The shortest time between two steps of a fade. A VCP write takes the
+/// display around 50ms to process, so this leaves room for the write itself
+/// and whatever else is talking to the display.
+const MIN_STEP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Stops a fade that is running on another thread.
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Steps a continuous VCP feature from its current value to `target` over
+/// `duration`. Returns whether the fade ran to completion, or `false` if it
+/// was cancelled part way.
+pub fn fade(
+    monitor: &Monitor,
+    code: u8,
+    target: u32,
+    duration: Duration,
+    cancel: &Cancel,
+) -> Result<bool, MonitorError> {
+    let from = i64::from(monitor.get_vcp(code)?.current);
+    let to = i64::from(target);
+
+    let max_steps = (duration.as_millis() / MIN_STEP_INTERVAL.as_millis()).max(1) as i64;
+    let steps = (to - from).abs().min(max_steps).max(1);
+    let interval = duration / steps as u32;
+    let start = Instant::now();
+
+    for step in 1..=steps {
+        if cancel.is_cancelled() {
+            return Ok(false);
+        }
+
+        monitor.set_vcp(code, (from + (to - from) * step / steps) as u32)?;
+
+        if step < steps {
+            // Sleep until the step is due rather than for a fixed interval,
+            // so that slow writes don't stretch out the fade.
+            let due = start + interval * step as u32;
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    Ok(true)
+}
+
+/// Dims the monitor over `duration` before putting it into `mode`. The
+/// original brightness is written back afterwards where the monitor still
+/// accepts it, so that it doesn't come back on at zero.
+pub fn fade_off(
+    monitor: &Monitor,
+    mode: PowerMode,
+    duration: Duration,
+    cancel: &Cancel,
+) -> Result<bool, MonitorError> {
+    let brightness = monitor.get_vcp(monitors::VCP_BRIGHTNESS)?.current;
+
+    if !fade(monitor, monitors::VCP_BRIGHTNESS, 0, duration, cancel)? {
+        return Ok(false);
+    }
+
+    monitor.set_power_mode(mode)?;
+
+    if let Err(e) = monitor.set_vcp(monitors::VCP_BRIGHTNESS, brightness) {
+        log::debug!(
+            "failed to restore brightness of monitor {}: {}",
+            monitor.id(),
+            e
+        );
+    }
+
+    Ok(true)
+}
+
+/// Keeps track of fades running in the background, so that they can be
+/// cancelled when something else wants to change the same monitor.
+#[derive(Default)]
+pub struct Fades {
+    running: Mutex<HashMap<String, Cancel>>,
+}
+
+impl Fades {
+    /// Cancels the fade running on the monitor, if there is one.
+    pub fn cancel(&self, monitor: &Monitor) {
+        if let Some(cancel) = self.running.lock().unwrap().remove(monitor.id()) {
+            log::info!("cancelling fade on monitor {}", monitor.id());
+            cancel.cancel();
+        }
+    }
+
+    /// Runs `f` on a new thread, cancelling any fade already running on the
+    /// monitor.
+    pub fn start<F>(self: &Arc<Self>, monitor: &Monitor, f: F)
+    where
+        F: FnOnce(&Monitor, &Cancel) -> Result<bool, MonitorError> + Send + 'static,
+    {
+        self.cancel(monitor);
+
+        let cancel = Cancel::default();
+        self.running
+            .lock()
+            .unwrap()
+            .insert(monitor.id().to_owned(), cancel.clone());
+
+        let fades = self.clone();
+        let monitor = monitor.clone();
+
+        thread::spawn(move || {
+            if let Err(e) = f(&monitor, &cancel) {
+                log::error!("fade on monitor {} failed: {}", monitor.id(), e);
+            }
+
+            // Only forget the fade if it hasn't already been replaced by a
+            // newer one.
+            let mut running = fades.running.lock().unwrap();
+            if running
+                .get(monitor.id())
+                .is_some_and(|c| Arc::ptr_eq(&c.0, &cancel.0))
+            {
+                running.remove(monitor.id());
+            }
+        });
+    }
+}
+
+/// Parses a duration such as `500ms`, `2s`, `1.5s` or `1m`.
+pub fn parse_duration(s: &str) -> Result<Duration, Box<dyn Error>> {
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .ok_or_else(|| format!("missing unit in duration: {}", s))?;
+
+    let (amount, unit) = s.split_at(split);
+    let amount: f64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration: {}", s))?;
+
+    let seconds = match unit {
+        "ms" => amount / 1000.0,
+        "s" => amount,
+        "m" => amount * 60.0,
+        _ => return Err(format!("invalid unit in duration: {}", s).into()),
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid duration: {}", s).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse_duration("0s").unwrap(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert!(parse_duration("5").is_err());
+        assert!(parse_duration("5h").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("1.2.3s").is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_durations() {
+        assert!(parse_duration("99999999999999999999999s").is_err());
+        assert!(parse_duration("99999999999999999999m").is_err());
+    }
+}
@@ -1,20 +1,135 @@
+mod adjustment;
+mod calibration;
+mod config;
 mod db;
+mod fade;
+#[cfg(windows)]
 mod installer;
 mod monitors;
+mod scene;
+mod selector;
 mod server;
+mod snapshot;
+#[cfg(windows)]
 mod win;
 
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt::Display;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
-use colored::Colorize;
+use colored::{ColoredString, Colorize};
 
-use monitors::PowerMode;
+use adjustment::Adjustment;
+use calibration::Calibration;
+use config::Config;
+use fade::Cancel;
+use monitors::{
+    ColorPreset, DisplayBackend, Edid, Extension, InputSource, Monitor, PowerMode, SimBackend,
+    VcpType,
+};
+use selector::{Aliases, Selector};
+use snapshot::{MonitorSnapshot, Snapshot};
 
 fn main() {
-    let matches = App::new(env!("CARGO_PKG_NAME"))
+    let app = App::new(env!("CARGO_PKG_NAME"))
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .version(env!("CARGO_PKG_VERSION"))
-        .subcommand(SubCommand::with_name("run").about("Runs the command server"))
-        .subcommand(SubCommand::with_name("list").about("Lists all connected monitors"))
+        .arg(
+            Arg::with_name("sim")
+                .long("sim")
+                .value_name("FIXTURE")
+                .global(true)
+                .help("Uses simulated monitors loaded from a TOML or JSON fixture"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .global(true)
+                .help(
+                    "Reads monitor aliases, groups and scenes from FILE instead of the default locations",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Runs the command server")
+                .arg(
+                    Arg::with_name("udp")
+                        .long("udp")
+                        .value_name("ADDR")
+                        .default_value("0.0.0.0:7890")
+                        .help("Listens for commands on ADDR"),
+                )
+                .arg(
+                    Arg::with_name("http")
+                        .long("http")
+                        .value_name("ADDR")
+                        .help("Also serves the HTTP API on ADDR, e.g. 0.0.0.0:7891"),
+                )
+                .arg(
+                    Arg::with_name("events")
+                        .long("events")
+                        .value_name("ADDR")
+                        .help("Also streams events to TCP clients on ADDR, e.g. 0.0.0.0:7892"),
+                )
+                .arg(
+                    Arg::with_name("poll-interval")
+                        .long("poll-interval")
+                        .value_name("DURATION")
+                        .default_value("2s")
+                        .help("How often to check monitors for changes while clients are subscribed to events"),
+                )
+                .arg(
+                    Arg::with_name("mqtt")
+                        .long("mqtt")
+                        .value_name("HOST[:PORT]")
+                        .help("Also publishes monitors to the MQTT broker at HOST, e.g. localhost:1883"),
+                )
+                .arg(
+                    Arg::with_name("mqtt-prefix")
+                        .long("mqtt-prefix")
+                        .value_name("TOPIC")
+                        .default_value("mona")
+                        .help("The topic that monitors are published under"),
+                )
+                .arg(
+                    Arg::with_name("mqtt-discovery-prefix")
+                        .long("mqtt-discovery-prefix")
+                        .value_name("TOPIC")
+                        .default_value("homeassistant")
+                        .help("The topic that Home Assistant discovery messages are published under"),
+                )
+                .arg(
+                    Arg::with_name("mqtt-username")
+                        .long("mqtt-username")
+                        .value_name("USERNAME")
+                        .requires("mqtt-password")
+                        .help("The username to log in to the MQTT broker with"),
+                )
+                .arg(
+                    Arg::with_name("mqtt-password")
+                        .long("mqtt-password")
+                        .value_name("PASSWORD")
+                        .requires("mqtt-username")
+                        .help("The password to log in to the MQTT broker with"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("Lists all connected monitors")
+                .arg(
+                    Arg::with_name("long")
+                        .long("long")
+                        .short("l")
+                        .help("Shows the identification data from each monitor's EDID"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("on")
                 .about("Turns on the specified monitor")
@@ -22,7 +137,9 @@ fn main() {
                     Arg::with_name("id")
                         .required(true)
                         .default_value("all")
-                        .help("The id of the monitor to turn on, or 'all' to turn on all monitors"),
+                        .help(
+                        "The monitors to turn on, e.g. 'all', '1,3', '1-3', 'DELL*' or 'all,!2'",
+                    ),
                 ),
         )
         .subcommand(
@@ -33,10 +150,165 @@ fn main() {
                         .required(true)
                         .default_value("all")
                         .help(
-                            "The id of the monitor to turn off, or 'all' to turn off all monitors",
+                        "The monitors to turn off, e.g. 'all', '1,3', '1-3', 'DELL*' or 'all,!2'",
+                    ),
+                )
+                .arg(Arg::with_name("hard").long("hard").help(
+                    "Turns the monitor off as if at the power button, instead of into \
+                     soft off, from which it turns back on when it receives a signal",
+                ))
+                .arg(fade_arg(
+                    "Dims the monitor over DURATION before turning it off, e.g. 2s",
+                )),
+        )
+        .subcommand(
+            level_subcommand(
+                "brightness",
+                "Gets or sets the brightness of the specified monitor",
+            )
+            .arg(Arg::with_name("raw").long("raw").help(
+                "Uses the value as is, instead of mapping it through the monitor's \
+                 calibration curve",
+            )),
+        )
+        .subcommand(level_subcommand(
+            "contrast",
+            "Gets or sets the contrast of the specified monitor",
+        ))
+        .subcommand(level_subcommand(
+            "volume",
+            "Gets or sets the speaker volume of the specified monitor",
+        ))
+        .subcommand(
+            SubCommand::with_name("mute")
+                .about("Mutes or unmutes the speakers of the specified monitor")
+                .arg(monitors_arg())
+                .arg(
+                    Arg::with_name("state")
+                        .possible_values(&["on", "off", "toggle"])
+                        .help("Whether to mute, unmute or toggle. Prints whether the monitor is muted if omitted"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("input")
+                .about("Gets or sets the input source of the specified monitor")
+                .arg(
+                    Arg::with_name("id")
+                        .required(true)
+                        .help("The monitors to use, e.g. 'all', '1,3', '1-3', 'DELL*' or 'all,!2'"),
+                )
+                .arg(Arg::with_name("source").help(
+                    "The input source, by name (e.g. hdmi1, dp1, usb-c) or raw value. \
+                     Prints the current input source if omitted",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("caps")
+                .about("Shows the MCCS capabilities of the specified monitor")
+                .arg(id_arg())
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints the capabilities as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("vcp")
+                .about("Reads or writes a VCP feature")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Reads the current and maximum value of a VCP feature")
+                        .arg(id_arg())
+                        .arg(code_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Sets the value of a VCP feature")
+                        .arg(id_arg())
+                        .arg(code_arg())
+                        .arg(
+                            Arg::with_name("value")
+                                .required(true)
+                                .help("The value to set"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("color")
+                .about("Gets or sets the color settings of the specified monitor")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Shows the color preset, gains and black levels")
+                        .arg(monitors_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("preset")
+                        .about("Gets or sets the color preset")
+                        .arg(monitors_arg())
+                        .arg(Arg::with_name("preset").help(
+                            "The preset, by name (e.g. srgb, native, 6500k, user1) or raw \
+                             value. Prints the current preset if omitted",
+                        )),
+                )
+                .subcommand(rgb_subcommand(
+                    "gain",
+                    "Gets or sets the red, green and blue video gains",
+                ))
+                .subcommand(rgb_subcommand(
+                    "black",
+                    "Gets or sets the red, green and blue black levels",
+                ))
+                .subcommand(
+                    SubCommand::with_name("temperature")
+                        .about(
+                            "Sets the color temperature, using a preset if the monitor has one \
+                             and approximating it with the gains otherwise",
+                        )
+                        .arg(monitors_arg())
+                        .arg(
+                            Arg::with_name("kelvin")
+                                .required(true)
+                                .help("The color temperature, e.g. 3400K"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("scene")
+                .about("Lists or applies the scenes defined in the config")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("list").about("Lists the defined scenes"))
+                .subcommand(
+                    SubCommand::with_name("apply")
+                        .about("Applies a scene")
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .help("The name of the scene"),
                         ),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("snapshot")
+                .about("Saves, restores or compares the settings of the specified monitors")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(snapshot_subcommand(
+                    "save",
+                    "Saves the current settings under the given name",
+                ))
+                .subcommand(snapshot_subcommand(
+                    "restore",
+                    "Writes back the settings saved under the given name",
+                ))
+                .subcommand(snapshot_subcommand(
+                    "diff",
+                    "Shows the settings that differ from the ones saved under the given name",
+                )),
+        );
+
+    #[cfg(windows)]
+    let app = app
         .subcommand(
             SubCommand::with_name("install")
                 .about("Installs the scheduled task to start mona on login"),
@@ -44,22 +316,215 @@ fn main() {
         .subcommand(
             SubCommand::with_name("uninstall")
                 .about("Removes the scheduled task to start mona on login"),
-        )
-        .get_matches();
+        );
+
+    let matches = app.get_matches();
+    let backend: Arc<dyn DisplayBackend> = match matches.value_of("sim") {
+        Some(path) => match SimBackend::from_file(path) {
+            Ok(backend) => Arc::new(backend),
+            Err(e) => exit_with_error(format!("failed to load fixture {}: {}", path, e)),
+        },
+        None => monitors::default_backend(),
+    };
+
+    let config = Config::load(matches.value_of("config").map(Path::new))
+        .unwrap_or_else(|e| exit_with_error(e));
+    let aliases = config.aliases().unwrap_or_else(|e| exit_with_error(e));
 
     match matches.subcommand() {
-        ("run", _) => server::run().unwrap(),
-        ("list", _) => list_monitors(),
-        ("on", Some(matches)) => set_power_mode(matches, PowerMode::On),
-        ("off", Some(matches)) => set_power_mode(matches, PowerMode::Off),
+        ("run", Some(matches)) => run_server(backend, config, matches),
+        ("list", Some(matches)) => list_monitors(&backend, matches.is_present("long")),
+        ("on", Some(matches)) => set_power_mode(&backend, &aliases, matches, PowerMode::On),
+        ("off", Some(matches)) => {
+            let mode = if matches.is_present("hard") {
+                PowerMode::HardOff
+            } else {
+                PowerMode::Off
+            };
+            set_power_mode(&backend, &aliases, matches, mode)
+        }
+        ("brightness", Some(matches)) => set_level(
+            &backend,
+            &config,
+            &aliases,
+            matches,
+            monitors::VCP_BRIGHTNESS,
+        ),
+        ("contrast", Some(matches)) => {
+            set_level(&backend, &config, &aliases, matches, monitors::VCP_CONTRAST)
+        }
+        ("volume", Some(matches)) => {
+            set_level(&backend, &config, &aliases, matches, monitors::VCP_VOLUME)
+        }
+        ("mute", Some(matches)) => set_mute(&backend, &aliases, matches),
+        ("input", Some(matches)) => set_input_source(&backend, &aliases, matches),
+        ("caps", Some(matches)) => show_capabilities(&backend, &aliases, matches),
+        ("vcp", Some(matches)) => match matches.subcommand() {
+            ("get", Some(matches)) => get_vcp(&backend, &aliases, matches),
+            ("set", Some(matches)) => set_vcp(&backend, &aliases, matches),
+            _ => {}
+        },
+        ("color", Some(matches)) => match matches.subcommand() {
+            ("get", Some(matches)) => show_color(&backend, &aliases, matches),
+            ("preset", Some(matches)) => set_color_preset(&backend, &aliases, matches),
+            ("gain", Some(matches)) => set_rgb(&backend, &aliases, matches, monitors::VCP_GAINS),
+            ("black", Some(matches)) => {
+                set_rgb(&backend, &aliases, matches, monitors::VCP_BLACK_LEVELS)
+            }
+            ("temperature", Some(matches)) => set_color_temperature(&backend, &aliases, matches),
+            _ => {}
+        },
+        ("scene", Some(matches)) => match matches.subcommand() {
+            ("list", _) => list_scenes(&config),
+            ("apply", Some(matches)) => apply_scene(&backend, &config, &aliases, matches),
+            _ => {}
+        },
+        ("snapshot", Some(matches)) => match matches.subcommand() {
+            ("save", Some(matches)) => save_snapshot(&backend, &aliases, matches),
+            ("restore", Some(matches)) => restore_snapshot(&backend, &aliases, matches),
+            ("diff", Some(matches)) => diff_snapshot(&backend, &aliases, matches),
+            _ => {}
+        },
+        #[cfg(windows)]
         ("install", _) => installer::install().unwrap(),
+        #[cfg(windows)]
         ("uninstall", _) => installer::uninstall().unwrap(),
         _ => {}
     }
 }
 
-fn list_monitors() {
-    let monitors = monitors::get_monitors();
+fn run_server(backend: Arc<dyn DisplayBackend>, config: Config, matches: &ArgMatches) {
+    let addr = |name| {
+        matches.value_of(name).map(|addr: &str| {
+            addr.parse()
+                .unwrap_or_else(|_| exit_with_error(format!("invalid address: {}", addr)))
+        })
+    };
+
+    let poll_interval = fade::parse_duration(matches.value_of("poll-interval").unwrap())
+        .unwrap_or_else(|e| exit_with_error(e));
+    if poll_interval == Duration::from_secs(0) {
+        exit_with_error("poll interval must be greater than zero");
+    }
+
+    let options = server::Options {
+        udp: addr("udp").unwrap(),
+        http: addr("http"),
+        events: addr("events"),
+        poll_interval,
+        mqtt: matches
+            .value_of("mqtt")
+            .map(|broker| mqtt_options(broker, matches)),
+    };
+
+    server::run(backend, config, options).unwrap_or_else(|e| exit_with_error(e));
+}
+
+fn mqtt_options(broker: &str, matches: &ArgMatches) -> server::MqttOptions {
+    let (host, port) = match broker.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .unwrap_or_else(|_| exit_with_error(format!("invalid port: {}", port))),
+        ),
+        None => (broker, server::DEFAULT_MQTT_PORT),
+    };
+
+    let credentials = matches
+        .value_of("mqtt-username")
+        .zip(matches.value_of("mqtt-password"))
+        .map(|(username, password)| (username.to_owned(), password.to_owned()));
+
+    server::MqttOptions {
+        host: host.to_owned(),
+        port,
+        prefix: matches.value_of("mqtt-prefix").unwrap().to_owned(),
+        discovery_prefix: matches
+            .value_of("mqtt-discovery-prefix")
+            .unwrap()
+            .to_owned(),
+        credentials,
+    }
+}
+
+fn level_subcommand(name: &'static str, about: &'static str) -> App<'static, 'static> {
+    SubCommand::with_name(name)
+        .about(about)
+        .setting(AppSettings::AllowLeadingHyphen)
+        .arg(
+            Arg::with_name("id")
+                .required(true)
+                .help("The monitors to use, e.g. 'all', '1,3', '1-3', 'DELL*' or 'all,!2'"),
+        )
+        .arg(Arg::with_name("value").allow_hyphen_values(true).help(
+            "The new value: absolute (40), a percentage of the maximum (40%), \
+                     or relative to the current value (+10, -5%). \
+                     Prints the current value if omitted",
+        ))
+        .arg(fade_arg(
+            "Changes the value gradually over DURATION, e.g. 500ms or 2s",
+        ))
+}
+
+fn fade_arg(help: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name("fade")
+        .long("fade")
+        .value_name("DURATION")
+        .help(help)
+}
+
+fn rgb_subcommand(name: &'static str, about: &'static str) -> App<'static, 'static> {
+    SubCommand::with_name(name)
+        .about(about)
+        .setting(AppSettings::AllowLeadingHyphen)
+        .arg(monitors_arg())
+        .arg(
+            Arg::with_name("values")
+                .multiple(true)
+                .max_values(3)
+                .allow_hyphen_values(true)
+                .help(
+                    "The new red, green and blue values, or one value for all three, \
+                     in the same forms as brightness. Prints the current values if omitted",
+                ),
+        )
+}
+
+fn monitors_arg() -> Arg<'static, 'static> {
+    Arg::with_name("id")
+        .required(true)
+        .help("The monitors to use, e.g. 'all', '1,3', '1-3', 'DELL*' or 'all,!2'")
+}
+
+fn snapshot_subcommand(name: &'static str, about: &'static str) -> App<'static, 'static> {
+    SubCommand::with_name(name)
+        .about(about)
+        .arg(
+            Arg::with_name("name")
+                .required(true)
+                .help("The name of the snapshot"),
+        )
+        .arg(
+            Arg::with_name("id")
+                .default_value("all")
+                .help("The monitors to use, e.g. 'all', '1,3', '1-3', 'DELL*' or 'all,!2'"),
+        )
+}
+
+fn id_arg() -> Arg<'static, 'static> {
+    Arg::with_name("id")
+        .required(true)
+        .help("The id or index of the monitor")
+}
+
+fn code_arg() -> Arg<'static, 'static> {
+    Arg::with_name("code")
+        .required(true)
+        .help("The VCP code, in decimal or 0x-prefixed hex")
+}
+
+fn list_monitors(backend: &Arc<dyn DisplayBackend>, long: bool) {
+    let monitors = get_monitors(backend);
 
     if monitors.is_empty() {
         println!("\nNo monitors found");
@@ -72,38 +537,744 @@ fn list_monitors() {
         "monitor(s) found:".yellow()
     );
 
-    println!("    id {} name", "|".bright_black());
+    let width = monitors.iter().map(|m| m.id().len()).max().unwrap_or(0);
+
+    println!(
+        "     # {} {:width$} {} input      {} name",
+        "|".bright_black(),
+        "id",
+        "|".bright_black(),
+        "|".bright_black(),
+        width = width
+    );
     println!(
         "{}",
-        "    --------------------------------------".bright_black()
+        format!("    {}", "-".repeat(width + 40)).bright_black()
     );
 
     for monitor in monitors {
+        let index = monitor.index().to_string();
         let id = monitor.id().to_string();
         let name = monitor.name().to_string();
+        let input = match monitor.input_source() {
+            Ok(source) => source.to_string(),
+            Err(_) => "-".to_owned(),
+        };
         let separator = "|".bright_black();
 
-        match monitor.power_mode() {
-            PowerMode::On => println!("    {:2} {} {}", id.green(), separator, name.green()),
-            PowerMode::Off => println!("    {:2} {} {}", id.red(), separator, name.red()),
+        let (paint, error): (fn(&str) -> ColoredString, _) = match monitor.power_mode() {
+            Ok(PowerMode::On) => (|s| s.green(), None),
+            Ok(PowerMode::Unknown(_)) => (|s| s.normal(), None),
+            Ok(_) => (|s| s.red(), None),
+            Err(e) => (|s| s.normal(), Some(e)),
         };
+
+        print!(
+            "    {:2} {} {:width$} {} {:10} {} {}",
+            paint(&index),
+            separator,
+            paint(&id),
+            separator,
+            input,
+            separator,
+            paint(&name),
+            width = width
+        );
+
+        match error {
+            Some(e) => println!(" {}", format!("({})", e).bright_black()),
+            None => println!(),
+        }
+
+        if long {
+            print_edid(monitor.edid());
+        }
+    }
+}
+
+fn print_edid(edid: Option<&Edid>) {
+    let separator = "|".bright_black();
+    let edid = match edid {
+        Some(edid) => edid,
+        None => {
+            println!("         {:12} {} -", "edid", separator);
+            return;
+        }
+    };
+
+    let manufactured = match edid.week {
+        _ if edid.model_year => format!("model year {}", edid.year),
+        Some(week) => format!("week {} of {}", week, edid.year),
+        None => edid.year.to_string(),
+    };
+
+    let mut details = vec![
+        ("manufacturer", edid.manufacturer.clone()),
+        ("product", format!("{:04X}", edid.product)),
+        ("serial", edid.serial().unwrap_or_else(|| "-".to_owned())),
+        ("manufactured", manufactured),
+        ("edid version", edid.version.clone()),
+    ];
+
+    if let Some((width, height)) = edid.size_cm {
+        details.push(("size", format!("{} x {} cm", width, height)));
+    }
+
+    if let Some(mode) = edid.preferred_mode {
+        details.push((
+            "native mode",
+            format!("{}x{} @ {}Hz", mode.width, mode.height, mode.refresh_hz),
+        ));
+    }
+
+    if !edid.extensions.is_empty() {
+        let extensions: Vec<_> = edid.extensions.iter().map(describe_extension).collect();
+        details.push(("extensions", extensions.join(", ")));
+    }
+
+    for (key, value) in details {
+        println!("         {:12} {} {}", key.bright_black(), separator, value);
     }
+
+    println!();
 }
 
-fn set_power_mode(matches: &ArgMatches, power_mode: PowerMode) {
-    let id = matches.value_of("id").unwrap();
-    let monitors = monitors::get_monitors();
-    if id == "all" {
-        monitors
+fn describe_extension(extension: &Extension) -> String {
+    match extension {
+        Extension::Cta(cta) => {
+            let features: Vec<_> = [
+                (cta.hdmi, "hdmi"),
+                (cta.hdmi_forum, "hdmi 2.x"),
+                (cta.hdr, "hdr"),
+                (cta.audio, "audio"),
+            ]
             .iter()
-            .for_each(|monitor| monitor.set_power_mode(power_mode).unwrap());
+            .filter(|(present, _)| *present)
+            .map(|(_, name)| *name)
+            .collect();
+
+            if features.is_empty() {
+                format!("cta-861 rev {}", cta.revision)
+            } else {
+                format!("cta-861 rev {} ({})", cta.revision, features.join(", "))
+            }
+        }
+        Extension::DisplayId { version } => format!("displayid {}", version),
+        Extension::BlockMap => "block map".to_owned(),
+        Extension::Unknown { tag } => format!("unknown (0x{:02x})", tag),
+    }
+}
+
+fn set_power_mode(
+    backend: &Arc<dyn DisplayBackend>,
+    aliases: &Aliases,
+    matches: &ArgMatches,
+    power_mode: PowerMode,
+) {
+    let fade = fade_duration(matches);
+    let monitors = get_monitors(backend);
+    let selected = select_monitors(&monitors, aliases, matches.value_of("id").unwrap());
+
+    match fade {
+        Some(duration) => report_each_concurrently(&selected, |monitor| {
+            fade::fade_off(monitor, power_mode, duration, &Cancel::default())?;
+            Ok("ok".to_owned())
+        }),
+        None => report_each(&selected, |monitor| {
+            monitor.set_power_mode(power_mode)?;
+            Ok("ok".to_owned())
+        }),
+    }
+
+    println!("\nOk 👍");
+}
+
+fn set_level(
+    backend: &Arc<dyn DisplayBackend>,
+    config: &Config,
+    aliases: &Aliases,
+    matches: &ArgMatches,
+    code: u8,
+) {
+    let adjustment: Option<Adjustment> = matches
+        .value_of("value")
+        .map(|value| value.parse().unwrap_or_else(|e| exit_with_error(e)));
+    let fade = fade_duration(matches);
+
+    let monitors = get_monitors(backend);
+    let selected = select_monitors(&monitors, aliases, matches.value_of("id").unwrap());
+    let calibration = if matches.is_present("raw") {
+        Calibration::default()
     } else {
-        let id: usize = id.parse().unwrap();
-        let monitor = monitors
-            .get(id - 1)
-            .expect("no monitor found with the given id");
-        monitor.set_power_mode(power_mode).unwrap();
+        config
+            .calibration(&monitors, aliases)
+            .unwrap_or_else(|e| exit_with_error(e))
+    };
+
+    report_each_concurrently(&selected, |monitor| {
+        let value = monitor.get_vcp(code)?;
+        match adjustment {
+            Some(adjustment) => {
+                let new = calibration.adjust(monitor, code, adjustment, &value);
+                match fade {
+                    Some(duration) => {
+                        fade::fade(monitor, code, new, duration, &Cancel::default())?;
+                    }
+                    None => monitor.set_vcp(code, new)?,
+                }
+                Ok(format!("{} -> {}", value.current, new))
+            }
+            None => match calibration.curve(monitor) {
+                Some(curve) if code == monitors::VCP_BRIGHTNESS => Ok(format!(
+                    "{} / {} (level {})",
+                    value.current,
+                    value.max,
+                    curve.level(value.current)
+                )),
+                _ => Ok(format!("{} / {}", value.current, value.max)),
+            },
+        }
+    });
+}
+
+fn fade_duration(matches: &ArgMatches) -> Option<Duration> {
+    matches
+        .value_of("fade")
+        .map(|fade| fade::parse_duration(fade).unwrap_or_else(|e| exit_with_error(e)))
+}
+
+fn show_capabilities(backend: &Arc<dyn DisplayBackend>, aliases: &Aliases, matches: &ArgMatches) {
+    let monitors = get_monitors(backend);
+    let monitor = find_monitor(&monitors, aliases, matches.value_of("id").unwrap());
+    let caps = monitor
+        .capabilities()
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    if matches.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&caps).unwrap());
+        return;
     }
 
+    let separator = "|".bright_black();
+    let unknown = || "-".to_owned();
+
+    println!(
+        "\n{} {}\n",
+        "Capabilities of".yellow(),
+        monitor.name().yellow()
+    );
+    println!(
+        "    protocol     {} {}",
+        separator,
+        caps.protocol.unwrap_or_else(unknown)
+    );
+    println!(
+        "    type         {} {}",
+        separator,
+        caps.kind.unwrap_or_else(unknown)
+    );
+    println!(
+        "    model        {} {}",
+        separator,
+        caps.model.unwrap_or_else(unknown)
+    );
+    println!(
+        "    mccs version {} {}",
+        separator,
+        caps.mccs_version.unwrap_or_else(unknown)
+    );
+
+    println!("\n{}\n", "VCP features:".yellow());
+
+    for vcp in caps.vcp {
+        let name = monitors::feature_name(vcp.code).unwrap_or("");
+        let values: Vec<_> = vcp.values.iter().map(|v| format!("{:02x}", v)).collect();
+
+        if values.is_empty() {
+            println!("    0x{:02x} {} {}", vcp.code, separator, name);
+        } else {
+            println!(
+                "    0x{:02x} {} {:36} {}",
+                vcp.code,
+                separator,
+                name,
+                values.join(" ").bright_black()
+            );
+        }
+    }
+}
+
+fn get_vcp(backend: &Arc<dyn DisplayBackend>, aliases: &Aliases, matches: &ArgMatches) {
+    let monitors = get_monitors(backend);
+    let monitor = find_monitor(&monitors, aliases, matches.value_of("id").unwrap());
+    let code = monitors::parse_vcp_code(matches.value_of("code").unwrap())
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    let value = monitor.get_vcp(code).unwrap_or_else(|e| exit_with_error(e));
+
+    let kind = match value.kind {
+        VcpType::SetParameter => "set parameter",
+        VcpType::Momentary => "momentary",
+    };
+
+    println!(
+        "\n{} {}\n",
+        format!("VCP 0x{:02x}", code).yellow(),
+        format!("({})", kind).bright_black()
+    );
+    println!("    current {} {}", "|".bright_black(), value.current);
+    println!("    max     {} {}", "|".bright_black(), value.max);
+}
+
+fn set_vcp(backend: &Arc<dyn DisplayBackend>, aliases: &Aliases, matches: &ArgMatches) {
+    let monitors = get_monitors(backend);
+    let monitor = find_monitor(&monitors, aliases, matches.value_of("id").unwrap());
+    let code = monitors::parse_vcp_code(matches.value_of("code").unwrap())
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    let value = matches.value_of("value").unwrap();
+    let value = value
+        .parse()
+        .unwrap_or_else(|_| exit_with_error(format!("invalid value: {}", value)));
+
+    monitor
+        .set_vcp(code, value)
+        .unwrap_or_else(|e| exit_with_error(e));
+
     println!("\nOk 👍");
 }
+
+fn set_input_source(backend: &Arc<dyn DisplayBackend>, aliases: &Aliases, matches: &ArgMatches) {
+    let source: Option<InputSource> = matches
+        .value_of("source")
+        .map(|source| source.parse().unwrap_or_else(|e| exit_with_error(e)));
+
+    let monitors = get_monitors(backend);
+    let selected = select_monitors(&monitors, aliases, matches.value_of("id").unwrap());
+
+    report_each(&selected, |monitor| match source {
+        Some(source) => {
+            monitor.set_input_source(source)?;
+            Ok(source.to_string())
+        }
+        None => Ok(monitor.input_source()?.to_string()),
+    });
+}
+
+fn set_mute(backend: &Arc<dyn DisplayBackend>, aliases: &Aliases, matches: &ArgMatches) {
+    let monitors = get_monitors(backend);
+    let selected = select_monitors(&monitors, aliases, matches.value_of("id").unwrap());
+
+    let describe = |muted| if muted { "muted" } else { "unmuted" }.to_owned();
+
+    report_each(&selected, |monitor| {
+        let muted = match matches.value_of("state") {
+            Some("on") => true,
+            Some("off") => false,
+            Some(_) => !monitor.muted()?,
+            None => return Ok(describe(monitor.muted()?)),
+        };
+
+        monitor.set_muted(muted)?;
+        Ok(describe(muted))
+    });
+}
+
+fn show_color(backend: &Arc<dyn DisplayBackend>, aliases: &Aliases, matches: &ArgMatches) {
+    let monitors = get_monitors(backend);
+    let selected = select_monitors(&monitors, aliases, matches.value_of("id").unwrap());
+
+    // Monitors often only support some of these, so show what they do
+    // support rather than failing.
+    report_each(&selected, |monitor| {
+        let preset = monitor
+            .color_preset()
+            .map_or_else(|_| "-".to_owned(), |preset| preset.to_string());
+        let rgb = |codes: [u8; 3]| {
+            codes
+                .iter()
+                .map(|&code| {
+                    monitor
+                        .get_vcp(code)
+                        .map_or_else(|_| "-".to_owned(), |value| value.current.to_string())
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        Ok(format!(
+            "preset {} {} gain {} {} black {}",
+            preset,
+            "|".bright_black(),
+            rgb(monitors::VCP_GAINS),
+            "|".bright_black(),
+            rgb(monitors::VCP_BLACK_LEVELS)
+        ))
+    });
+}
+
+fn set_color_preset(backend: &Arc<dyn DisplayBackend>, aliases: &Aliases, matches: &ArgMatches) {
+    let preset: Option<ColorPreset> = matches
+        .value_of("preset")
+        .map(|preset| preset.parse().unwrap_or_else(|e| exit_with_error(e)));
+
+    let monitors = get_monitors(backend);
+    let selected = select_monitors(&monitors, aliases, matches.value_of("id").unwrap());
+
+    report_each(&selected, |monitor| match preset {
+        Some(preset) => {
+            monitor.set_color_preset(preset)?;
+            Ok(preset.to_string())
+        }
+        None => Ok(monitor.color_preset()?.to_string()),
+    });
+}
+
+fn set_rgb(
+    backend: &Arc<dyn DisplayBackend>,
+    aliases: &Aliases,
+    matches: &ArgMatches,
+    codes: [u8; 3],
+) {
+    let adjustments: Vec<Adjustment> = matches
+        .values_of("values")
+        .into_iter()
+        .flatten()
+        .map(|value| value.parse().unwrap_or_else(|e| exit_with_error(e)))
+        .collect();
+
+    let adjustments = match adjustments.as_slice() {
+        [] => None,
+        [all] => Some([*all; 3]),
+        [r, g, b] => Some([*r, *g, *b]),
+        _ => exit_with_error("expected one value, or one each for red, green and blue"),
+    };
+
+    let monitors = get_monitors(backend);
+    let selected = select_monitors(&monitors, aliases, matches.value_of("id").unwrap());
+
+    let join = |values: &[u32]| {
+        let values: Vec<_> = values.iter().map(|v| v.to_string()).collect();
+        values.join(" ")
+    };
+
+    report_each(&selected, |monitor| {
+        // Read all three first, so that nothing is changed if one of them
+        // isn't supported.
+        let values = codes
+            .iter()
+            .map(|&code| monitor.get_vcp(code))
+            .collect::<Result<Vec<_>, _>>()?;
+        let current: Vec<_> = values.iter().map(|value| value.current).collect();
+
+        let adjustments = match adjustments {
+            Some(adjustments) => adjustments,
+            None => return Ok(join(&current)),
+        };
+
+        let mut new = vec![];
+        for ((&code, value), adjustment) in codes.iter().zip(&values).zip(&adjustments) {
+            let value = adjustment.apply(value.current, value.max);
+            monitor.set_vcp(code, value)?;
+            new.push(value);
+        }
+
+        Ok(format!("{} -> {}", join(&current), join(&new)))
+    });
+}
+
+fn set_color_temperature(
+    backend: &Arc<dyn DisplayBackend>,
+    aliases: &Aliases,
+    matches: &ArgMatches,
+) {
+    let kelvin = monitors::parse_kelvin(matches.value_of("kelvin").unwrap())
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    let monitors = get_monitors(backend);
+    let selected = select_monitors(&monitors, aliases, matches.value_of("id").unwrap());
+
+    report_each(&selected, |monitor| {
+        Ok(monitor.set_color_temperature(kelvin)?.to_string())
+    });
+}
+
+fn list_scenes(config: &Config) {
+    if config.scenes.is_empty() {
+        println!("\nNo scenes defined");
+        return;
+    }
+
+    println!();
+
+    for (name, scene) in &config.scenes {
+        println!("    {}", name.yellow());
+        for step in scene {
+            let actions = match step.actions() {
+                Ok(actions) => actions.iter().map(|a| a.to_string()).collect(),
+                Err(e) => vec![e.to_string().red().to_string()],
+            };
+            println!(
+                "        {} {} {}",
+                step.monitors,
+                "|".bright_black(),
+                actions.join(", ")
+            );
+        }
+    }
+}
+
+fn apply_scene(
+    backend: &Arc<dyn DisplayBackend>,
+    config: &Config,
+    aliases: &Aliases,
+    matches: &ArgMatches,
+) {
+    let scene = config
+        .scene(matches.value_of("name").unwrap())
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    let monitors = get_monitors(backend);
+    let calibration = config
+        .calibration(&monitors, aliases)
+        .unwrap_or_else(|e| exit_with_error(e));
+    let results = scene::apply(scene, &monitors, aliases, &calibration)
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    let mut failed = false;
+    let separator = "|".bright_black();
+
+    println!();
+
+    for step in results {
+        let index = step.monitor.index().to_string();
+        let name = step.monitor.name();
+        let action = step.action.to_string();
+
+        match step.result {
+            Ok(res) => println!(
+                "    {:2} {} {} {} {:20} {} {}",
+                index, separator, name, separator, action, separator, res
+            ),
+            Err(e) => {
+                failed = true;
+                println!(
+                    "    {:2} {} {} {} {:20} {} {}",
+                    index.red(),
+                    separator,
+                    name.red(),
+                    separator,
+                    action,
+                    separator,
+                    e.to_string().red()
+                );
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn save_snapshot(backend: &Arc<dyn DisplayBackend>, aliases: &Aliases, matches: &ArgMatches) {
+    let name = matches.value_of("name").unwrap();
+    let monitors = get_monitors(backend);
+    let selected = select_monitors(&monitors, aliases, matches.value_of("id").unwrap());
+
+    let snapshot = RefCell::new(Snapshot::default());
+    let ok = try_each(&selected, |monitor| {
+        let saved = MonitorSnapshot::capture(monitor)?;
+        let res = format!("{} settings saved", saved.vcp.len());
+        snapshot
+            .borrow_mut()
+            .monitors
+            .insert(monitor.id().to_owned(), saved);
+        Ok(res)
+    });
+
+    // Save whatever could be read, so that one monitor without DDC/CI
+    // doesn't stop the rest from being saved.
+    match snapshot.into_inner().save(name) {
+        Ok(path) => println!("\nSaved to {}", path.display()),
+        Err(e) => exit_with_error(format!("failed to save snapshot: {}", e)),
+    }
+
+    if !ok {
+        process::exit(1);
+    }
+}
+
+fn restore_snapshot(backend: &Arc<dyn DisplayBackend>, aliases: &Aliases, matches: &ArgMatches) {
+    let snapshot =
+        Snapshot::load(matches.value_of("name").unwrap()).unwrap_or_else(|e| exit_with_error(e));
+    let monitors = get_monitors(backend);
+    let selected = select_monitors(&monitors, aliases, matches.value_of("id").unwrap());
+
+    report_each(&selected, |monitor| {
+        let saved = snapshot
+            .monitors
+            .get(monitor.id())
+            .ok_or("not in snapshot")?;
+
+        let differences = saved.diff(monitor)?;
+        for difference in &differences {
+            monitor
+                .set_vcp(difference.code, difference.saved)
+                .map_err(|e| format!("failed to restore 0x{:02x}: {}", difference.code, e))?;
+        }
+
+        Ok(format!("{} settings restored", differences.len()))
+    });
+}
+
+fn diff_snapshot(backend: &Arc<dyn DisplayBackend>, aliases: &Aliases, matches: &ArgMatches) {
+    let snapshot =
+        Snapshot::load(matches.value_of("name").unwrap()).unwrap_or_else(|e| exit_with_error(e));
+    let monitors = get_monitors(backend);
+    let selected = select_monitors(&monitors, aliases, matches.value_of("id").unwrap());
+
+    report_each(&selected, |monitor| {
+        let saved = snapshot
+            .monitors
+            .get(monitor.id())
+            .ok_or("not in snapshot")?;
+
+        let differences = saved.diff(monitor)?;
+        if differences.is_empty() {
+            return Ok("unchanged".to_owned());
+        }
+
+        let mut res = format!("{} changed", differences.len());
+        for difference in differences {
+            let current = match difference.current {
+                Ok(current) => current.to_string(),
+                Err(e) => e.to_string(),
+            };
+            res.push_str(&format!(
+                "\n         0x{:02x} {:28} {} -> {}",
+                difference.code,
+                monitors::feature_name(difference.code).unwrap_or(""),
+                difference.saved,
+                current
+            ));
+        }
+
+        Ok(res)
+    });
+}
+
+/// Runs `f` against each of the monitors, printing its result or error next
+/// to the monitor. Exits with a non-zero status if any of them failed.
+fn report_each(monitors: &[&Monitor], f: impl Fn(&Monitor) -> Result<String, Box<dyn Error>>) {
+    if !try_each(monitors, f) {
+        process::exit(1);
+    }
+}
+
+/// Like `report_each`, but returns whether all of them succeeded instead of
+/// exiting.
+fn try_each(monitors: &[&Monitor], f: impl Fn(&Monitor) -> Result<String, Box<dyn Error>>) -> bool {
+    println!();
+
+    let mut ok = true;
+    for monitor in monitors {
+        ok &= print_result(monitor, f(monitor).map_err(|e| e.to_string()));
+    }
+
+    ok
+}
+
+/// Like `report_each`, but runs `f` against all of the monitors at the same
+/// time, for changes that take a while such as fades.
+fn report_each_concurrently(
+    monitors: &[&Monitor],
+    f: impl Fn(&Monitor) -> Result<String, Box<dyn Error>> + Sync,
+) {
+    let f = &f;
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = monitors
+            .iter()
+            .map(|&monitor| scope.spawn(move || f(monitor).map_err(|e| e.to_string())))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    println!();
+
+    let mut ok = true;
+    for (monitor, result) in monitors.iter().zip(results) {
+        ok &= print_result(monitor, result);
+    }
+
+    if !ok {
+        process::exit(1);
+    }
+}
+
+/// Prints the result next to the monitor, returning whether it succeeded.
+fn print_result(monitor: &Monitor, result: Result<String, String>) -> bool {
+    let id = monitor.index().to_string();
+    let separator = "|".bright_black();
+
+    match result {
+        Ok(res) => {
+            println!(
+                "    {:2} {} {} {} {}",
+                id,
+                separator,
+                monitor.name(),
+                separator,
+                res
+            );
+            true
+        }
+        Err(e) => {
+            println!(
+                "    {:2} {} {} {} {}",
+                id.red(),
+                separator,
+                monitor.name().red(),
+                separator,
+                e.red()
+            );
+            false
+        }
+    }
+}
+
+/// Returns the connected monitors, exiting if they can't be enumerated.
+fn get_monitors(backend: &Arc<dyn DisplayBackend>) -> Vec<Monitor> {
+    monitors::get_monitors(backend)
+        .unwrap_or_else(|e| exit_with_error(format!("failed to enumerate monitors: {}", e)))
+}
+
+/// Returns the monitors matching the selector, see `Selector` for the syntax.
+fn select_monitors<'a>(
+    monitors: &'a [Monitor],
+    aliases: &Aliases,
+    selector: &str,
+) -> Vec<&'a Monitor> {
+    selector
+        .parse::<Selector>()
+        .and_then(|selector| selector.select(monitors, aliases))
+        .unwrap_or_else(|e| exit_with_error(e))
+}
+
+/// Returns the single monitor matching the selector.
+fn find_monitor<'a>(monitors: &'a [Monitor], aliases: &Aliases, selector: &str) -> &'a Monitor {
+    selector
+        .parse::<Selector>()
+        .and_then(|selector| selector.select_one(monitors, aliases))
+        .unwrap_or_else(|e| exit_with_error(e))
+}
+
+/// Prints the error and exits with a non-zero status.
+fn exit_with_error(e: impl Display) -> ! {
+    eprintln!("\n{} {}", "Error:".red(), e);
+    process::exit(1);
+}
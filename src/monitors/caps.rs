@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::MonitorError;
+
+/// A parsed MCCS capabilities string, e.g.
+///
+/// ```text
+/// (prot(monitor)type(LCD)model(U2720Q)cmds(01 02 03 07 0C E3 F3)
+///  vcp(02 04 10 12 14(04 05 08) 60(0F 11 1B) D6(01 04 05))mccs_ver(2.1))
+/// ```
+#[derive(Clone, Debug, Serialize)]
+pub struct Capabilities {
+    pub protocol: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub model: Option<String>,
+    pub mccs_version: Option<String>,
+    pub commands: Vec<u8>,
+    pub vcp: Vec<VcpCapability>,
+    /// Any other top level entries, keyed by name.
+    pub other: BTreeMap<String, String>,
+    pub raw: String,
+}
+
+/// A VCP feature supported by the monitor, along with the values it accepts
+/// if it is non-continuous.
+#[derive(Clone, Debug, Serialize)]
+pub struct VcpCapability {
+    pub code: u8,
+    pub values: Vec<u8>,
+}
+
+impl Capabilities {
+    pub fn parse(raw: &str) -> Result<Capabilities, MonitorError> {
+        let mut caps = Capabilities {
+            protocol: None,
+            kind: None,
+            model: None,
+            mccs_version: None,
+            commands: vec![],
+            vcp: vec![],
+            other: BTreeMap::new(),
+            raw: raw.to_owned(),
+        };
+
+        // The whole string is usually wrapped in parentheses, but some
+        // monitors leave them out.
+        let mut body = raw.trim().trim_end_matches('\0');
+        if body.starts_with('(') && matching_paren(body, 0) == Some(body.len() - 1) {
+            body = &body[1..body.len() - 1];
+        }
+
+        for (key, value) in entries(body)? {
+            match key.as_str() {
+                "prot" => caps.protocol = Some(value.trim().to_owned()),
+                "type" => caps.kind = Some(value.trim().to_owned()),
+                "model" => caps.model = Some(value.trim().to_owned()),
+                "mccs_ver" => caps.mccs_version = Some(value.trim().to_owned()),
+                "cmds" => {
+                    caps.commands = parse_vcp_list(value)?.into_iter().map(|c| c.code).collect()
+                }
+                "vcp" => caps.vcp = parse_vcp_list(value)?,
+                _ => {
+                    caps.other.insert(key, value.to_owned());
+                }
+            }
+        }
+
+        Ok(caps)
+    }
+}
+
+/// Splits a string of the form `key(value)key(value)...` into its entries.
+fn entries(s: &str) -> Result<Vec<(String, &str)>, MonitorError> {
+    let mut entries = vec![];
+    let mut pos = 0;
+
+    while pos < s.len() {
+        let open = match s[pos..].find('(') {
+            Some(i) => pos + i,
+            None => break,
+        };
+
+        let close = matching_paren(s, open)
+            .ok_or_else(|| invalid(format!("unbalanced parentheses in capabilities: {}", s)))?;
+
+        let key = s[pos..open].trim().to_lowercase();
+        entries.push((key, &s[open + 1..close]));
+        pos = close + 1;
+    }
+
+    Ok(entries)
+}
+
+/// Parses a list of hex codes, each optionally followed by a parenthesised
+/// list of hex values, e.g. `10 12 14(04 05 08) 60( 0F 11 )`.
+fn parse_vcp_list(s: &str) -> Result<Vec<VcpCapability>, MonitorError> {
+    let mut list: Vec<VcpCapability> = vec![];
+    let mut pos = 0;
+
+    while pos < s.len() {
+        let c = s[pos..].chars().next().unwrap();
+
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+        } else if c == '(' {
+            let close = matching_paren(s, pos)
+                .ok_or_else(|| invalid(format!("unbalanced parentheses in vcp list: {}", s)))?;
+            let values = parse_hex_bytes(&s[pos + 1..close])?;
+            match list.last_mut() {
+                Some(last) => last.values = values,
+                None => return Err(invalid(format!("vcp values without a code: {}", s))),
+            }
+            pos = close + 1;
+        } else {
+            let len = s[pos..]
+                .find(|c: char| c.is_whitespace() || c == '(')
+                .unwrap_or(s.len() - pos);
+            list.push(VcpCapability {
+                code: parse_hex_byte(&s[pos..pos + len])?,
+                values: vec![],
+            });
+            pos += len;
+        }
+    }
+
+    Ok(list)
+}
+
+fn parse_hex_bytes(s: &str) -> Result<Vec<u8>, MonitorError> {
+    s.split_whitespace().map(parse_hex_byte).collect()
+}
+
+fn parse_hex_byte(s: &str) -> Result<u8, MonitorError> {
+    u8::from_str_radix(s, 16)
+        .map_err(|_| invalid(format!("invalid hex byte in capabilities: {}", s)))
+}
+
+fn invalid(message: String) -> MonitorError {
+    MonitorError::Protocol(message)
+}
+
+/// Returns the index of the parenthesis closing the one at `open`.
+fn matching_paren(s: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s[open..].char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open + i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the MCCS name of common VCP features.
+pub fn feature_name(code: u8) -> Option<&'static str> {
+    let name = match code {
+        0x02 => "new control value",
+        0x04 => "restore factory defaults",
+        0x05 => "restore factory brightness/contrast",
+        0x06 => "restore factory geometry",
+        0x08 => "restore factory color",
+        0x0b => "color temperature increment",
+        0x0c => "color temperature request",
+        0x10 => "brightness",
+        0x12 => "contrast",
+        0x14 => "select color preset",
+        0x16 => "video gain: red",
+        0x18 => "video gain: green",
+        0x1a => "video gain: blue",
+        0x52 => "active control",
+        0x60 => "input source",
+        0x62 => "audio speaker volume",
+        0x6c => "video black level: red",
+        0x6e => "video black level: green",
+        0x70 => "video black level: blue",
+        0x8d => "audio mute",
+        0xaa => "screen orientation",
+        0xac => "horizontal frequency",
+        0xae => "vertical frequency",
+        0xb2 => "flat panel sub-pixel layout",
+        0xb6 => "display technology type",
+        0xc0 => "display usage time",
+        0xc6 => "application enable key",
+        0xc8 => "display controller type",
+        0xc9 => "display firmware level",
+        0xca => "osd",
+        0xcc => "osd language",
+        0xd6 => "power mode",
+        0xdc => "display mode",
+        0xdf => "vcp version",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELL: &str = "(prot(monitor)type(LCD)model(U2720Q)cmds(01 02 03 07 0C E3 F3)\
+        vcp(02 04 10 12 14(04 05 08) 60( 0F 11 1B ) D6(01 04 05))mccs_ver(2.1)\
+        mswhql(1))";
+
+    fn codes(vcp: &[VcpCapability]) -> Vec<u8> {
+        vcp.iter().map(|feature| feature.code).collect()
+    }
+
+    #[test]
+    fn parses_capabilities() {
+        let caps = Capabilities::parse(DELL).unwrap();
+
+        assert_eq!(caps.protocol.as_deref(), Some("monitor"));
+        assert_eq!(caps.kind.as_deref(), Some("LCD"));
+        assert_eq!(caps.model.as_deref(), Some("U2720Q"));
+        assert_eq!(caps.mccs_version.as_deref(), Some("2.1"));
+        assert_eq!(caps.commands, [0x01, 0x02, 0x03, 0x07, 0x0c, 0xe3, 0xf3]);
+        assert_eq!(codes(&caps.vcp), [0x02, 0x04, 0x10, 0x12, 0x14, 0x60, 0xd6]);
+        assert_eq!(caps.other.get("mswhql").map(String::as_str), Some("1"));
+        assert_eq!(caps.raw, DELL);
+    }
+
+    #[test]
+    fn parses_nested_values() {
+        let caps = Capabilities::parse(DELL).unwrap();
+        let values = |code: u8| {
+            caps.vcp
+                .iter()
+                .find(|feature| feature.code == code)
+                .unwrap()
+                .values
+                .clone()
+        };
+
+        assert!(values(0x10).is_empty());
+        assert_eq!(values(0x14), [0x04, 0x05, 0x08]);
+        assert_eq!(values(0x60), [0x0f, 0x11, 0x1b]);
+        assert_eq!(values(0xd6), [0x01, 0x04, 0x05]);
+    }
+
+    #[test]
+    fn parses_unwrapped_strings() {
+        let caps = Capabilities::parse(" prot(monitor) vcp(10 12 60(11 12))\0\0").unwrap();
+
+        assert_eq!(caps.protocol.as_deref(), Some("monitor"));
+        assert_eq!(codes(&caps.vcp), [0x10, 0x12, 0x60]);
+        assert_eq!(caps.vcp[2].values, [0x11, 0x12]);
+    }
+
+    #[test]
+    fn keeps_entries_that_only_look_wrapped() {
+        // The first parenthesis closes before the end, so the string isn't
+        // wrapped and its first entry has no name.
+        let caps = Capabilities::parse("(prot(monitor))vcp(10)").unwrap();
+
+        assert_eq!(
+            caps.other.get("").map(String::as_str),
+            Some("prot(monitor)")
+        );
+        assert_eq!(codes(&caps.vcp), [0x10]);
+    }
+
+    #[test]
+    fn rejects_malformed_strings() {
+        for raw in &[
+            "(prot(monitor)vcp(10 12)",
+            "vcp(10 12(04 05)",
+            "vcp(10 1G)",
+            "vcp(100)",
+            "vcp((04 05) 10)",
+            "cmds(01 ZZ)",
+        ] {
+            assert!(
+                matches!(Capabilities::parse(raw), Err(MonitorError::Protocol(_))),
+                "{} should have been rejected",
+                raw
+            );
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer};
+
+pub const VCP_COLOR_PRESET: u8 = 0x14;
+/// The red, green and blue video gains.
+pub const VCP_GAINS: [u8; 3] = [0x16, 0x18, 0x1a];
+/// The red, green and blue video black levels.
+pub const VCP_BLACK_LEVELS: [u8; 3] = [0x6c, 0x6e, 0x70];
+
+/// Color preset names and their VCP 0x14 values, as defined by MCCS.
+const COLOR_PRESETS: &[(&str, u8)] = &[
+    ("srgb", 0x01),
+    ("native", 0x02),
+    ("4000k", 0x03),
+    ("5000k", 0x04),
+    ("6500k", 0x05),
+    ("7500k", 0x06),
+    ("8200k", 0x07),
+    ("9300k", 0x08),
+    ("10000k", 0x09),
+    ("11500k", 0x0a),
+    ("user1", 0x0b),
+    ("user2", 0x0c),
+    ("user3", 0x0d),
+];
+
+// The range that `temperature_gains` gives sensible results for.
+const MIN_KELVIN: u32 = 1000;
+const MAX_KELVIN: u32 = 40000;
+
+// The white point that gains are relative to.
+const REFERENCE_KELVIN: u32 = 6500;
+
+/// A value of the select color preset VCP feature (0x14).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorPreset(pub u8);
+
+impl ColorPreset {
+    /// The preset that most monitors need to be in for the gains to be
+    /// adjustable.
+    pub const USER1: ColorPreset = ColorPreset(0x0b);
+
+    pub fn name(&self) -> Option<&'static str> {
+        COLOR_PRESETS
+            .iter()
+            .find(|(_, value)| *value == self.0)
+            .map(|(name, _)| *name)
+    }
+
+    /// The preset for a color temperature, if MCCS defines one.
+    pub fn for_kelvin(kelvin: u32) -> Option<ColorPreset> {
+        format!("{}k", kelvin).parse().ok()
+    }
+}
+
+impl fmt::Display for ColorPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "0x{:02x}", self.0),
+        }
+    }
+}
+
+impl FromStr for ColorPreset {
+    type Err = Box<dyn Error>;
+
+    /// Parses either a preset name or a raw value.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_lowercase();
+        if let Some((_, value)) = COLOR_PRESETS.iter().find(|(n, _)| *n == name) {
+            return Ok(ColorPreset(*value));
+        }
+
+        super::parse_vcp_code(s)
+            .map(ColorPreset)
+            .map_err(|_| format!("unknown color preset: {}", s).into())
+    }
+}
+
+/// Deserializes from either a raw value or a string in any of the forms
+/// accepted by `from_str`.
+impl<'de> Deserialize<'de> for ColorPreset {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u8),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(value) => Ok(ColorPreset(value)),
+            Raw::Text(s) => s.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+/// How a color temperature was applied to a monitor.
+#[derive(Copy, Clone, Debug)]
+pub enum Temperature {
+    Preset(ColorPreset),
+    Gains([u32; 3]),
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Temperature::Preset(preset) => write!(f, "preset {}", preset),
+            Temperature::Gains([r, g, b]) => write!(f, "gains {} {} {}", r, g, b),
+        }
+    }
+}
+
+/// Parses a color temperature such as `3400`, `3400k` or `3400K`.
+pub fn parse_kelvin(s: &str) -> Result<u32, Box<dyn Error>> {
+    let digits = s.strip_suffix(|c| c == 'k' || c == 'K').unwrap_or(s);
+    let kelvin: u32 = digits
+        .parse()
+        .map_err(|_| format!("invalid color temperature: {}", s))?;
+
+    if !(MIN_KELVIN..=MAX_KELVIN).contains(&kelvin) {
+        return Err(format!(
+            "color temperature must be between {}K and {}K",
+            MIN_KELVIN, MAX_KELVIN
+        )
+        .into());
+    }
+
+    Ok(kelvin)
+}
+
+/// Approximates a color temperature as red, green and blue gains between 0
+/// and 1, relative to a 6500K white point. The brightest channel is always 1,
+/// so that warming the picture up dims blue and green rather than boosting
+/// red.
+pub fn temperature_gains(kelvin: u32) -> [f64; 3] {
+    let target = blackbody(kelvin);
+    let reference = blackbody(REFERENCE_KELVIN);
+
+    let mut gains = [0.0; 3];
+    for ((gain, target), reference) in gains.iter_mut().zip(&target).zip(&reference) {
+        *gain = target / reference;
+    }
+
+    let brightest = gains.iter().cloned().fold(f64::MIN, f64::max);
+    gains.map(|gain| gain / brightest)
+}
+
+/// The color of a black body at the given temperature, using Tanner
+/// Helland's fit to the CIE 1964 color matching functions.
+fn blackbody(kelvin: u32) -> [f64; 3] {
+    let t = f64::from(kelvin.clamp(MIN_KELVIN, MAX_KELVIN)) / 100.0;
+
+    let red = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_727_446 * (t - 60.0).powf(-0.133_204_759_2)
+    };
+
+    let green = if t <= 66.0 {
+        99.470_802_586_1 * t.ln() - 161.119_568_166_1
+    } else {
+        288.122_169_528_3 * (t - 60.0).powf(-0.075_514_849_2)
+    };
+
+    let blue = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_731_223_1 * (t - 10.0).ln() - 305.044_792_730_7
+    };
+
+    [red, green, blue].map(|c| c.clamp(0.0, 255.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_displays_presets() {
+        assert_eq!("sRGB".parse::<ColorPreset>().unwrap(), ColorPreset(0x01));
+        assert_eq!("6500K".parse::<ColorPreset>().unwrap(), ColorPreset(0x05));
+        assert_eq!("user1".parse::<ColorPreset>().unwrap(), ColorPreset::USER1);
+        assert_eq!("0x0e".parse::<ColorPreset>().unwrap(), ColorPreset(0x0e));
+        assert_eq!("11".parse::<ColorPreset>().unwrap(), ColorPreset(0x0b));
+        assert!("warm".parse::<ColorPreset>().is_err());
+
+        assert_eq!(ColorPreset(0x05).to_string(), "6500k");
+        assert_eq!(ColorPreset(0x0e).to_string(), "0x0e");
+
+        let presets: Vec<ColorPreset> = serde_json::from_str(r#"[5, "native"]"#).unwrap();
+        assert_eq!(presets, [ColorPreset(0x05), ColorPreset(0x02)]);
+    }
+
+    #[test]
+    fn finds_presets_for_temperatures() {
+        assert_eq!(ColorPreset::for_kelvin(6500), Some(ColorPreset(0x05)));
+        assert_eq!(ColorPreset::for_kelvin(10000), Some(ColorPreset(0x09)));
+        assert_eq!(ColorPreset::for_kelvin(6000), None);
+    }
+
+    #[test]
+    fn parses_kelvin() {
+        assert_eq!(parse_kelvin("3400").unwrap(), 3400);
+        assert_eq!(parse_kelvin("3400k").unwrap(), 3400);
+        assert_eq!(parse_kelvin("3400K").unwrap(), 3400);
+        assert_eq!(parse_kelvin("1000").unwrap(), MIN_KELVIN);
+        assert_eq!(parse_kelvin("40000k").unwrap(), MAX_KELVIN);
+
+        assert!(parse_kelvin("999").is_err());
+        assert!(parse_kelvin("40001").is_err());
+        assert!(parse_kelvin("k").is_err());
+        assert!(parse_kelvin("3400kk").is_err());
+        assert!(parse_kelvin("-3400").is_err());
+    }
+
+    #[test]
+    fn keeps_the_reference_white() {
+        assert_eq!(temperature_gains(REFERENCE_KELVIN), [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn dims_blue_first_when_warming() {
+        for kelvin in [2000, 3400, 5000, 6000] {
+            let [red, green, blue] = temperature_gains(kelvin);
+            assert_eq!(red, 1.0, "{}K", kelvin);
+            assert!(blue < green && green < 1.0, "{}K", kelvin);
+        }
+
+        let [red, _, blue] = temperature_gains(9300);
+        assert!(red < 1.0);
+        assert_eq!(blue, 1.0);
+    }
+
+    #[test]
+    fn keeps_the_brightest_channel_at_full_gain() {
+        for kelvin in (MIN_KELVIN..=MAX_KELVIN).step_by(500) {
+            let gains = temperature_gains(kelvin);
+            let brightest = gains.iter().cloned().fold(f64::MIN, f64::max);
+            assert_eq!(brightest, 1.0, "{}K", kelvin);
+            assert!(gains.iter().all(|&gain| gain >= 0.0), "{}K", kelvin);
+        }
+    }
+}
@@ -0,0 +1,422 @@
+//! Encoding and decoding of DDC/CI messages, as sent over I2C.
+//!
+//! Messages written by the host start with the host's source address and are
+//! checksummed together with the display's write address (`0x6e`), which the
+//! I2C layer sends on our behalf. Replies read back from the display start
+//! with the display's source address and are checksummed against the host's
+//! virtual read address (`0x50`).
+
+use std::error::Error;
+use std::fmt;
+
+/// The I2C slave address that displays accept DDC/CI messages on.
+pub const I2C_ADDR: u16 = 0x37;
+
+const HOST_ADDR: u8 = 0x51;
+const DISPLAY_ADDR: u8 = 0x6e;
+const HOST_READ_ADDR: u8 = 0x50;
+
+const GET_VCP_REQUEST: u8 = 0x01;
+const GET_VCP_REPLY: u8 = 0x02;
+const SET_VCP_REQUEST: u8 = 0x03;
+const TIMING_REQUEST: u8 = 0x07;
+const TIMING_REPLY: u8 = 0x4e;
+const CAPABILITIES_REQUEST: u8 = 0xf3;
+const CAPABILITIES_REPLY: u8 = 0xe3;
+
+/// The type byte of a VCP reply for features that trigger an action rather
+/// than holding a value.
+pub const VCP_TYPE_MOMENTARY: u8 = 0x01;
+
+/// The largest number of capabilities string bytes in a single reply.
+pub const MAX_CAPABILITIES_FRAGMENT: usize = 32;
+
+/// The largest reply a display can send, including framing.
+pub const MAX_REPLY_LEN: usize = MAX_CAPABILITIES_FRAGMENT + 6;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    GetVcp {
+        code: u8,
+    },
+    SetVcp {
+        code: u8,
+        value: u16,
+    },
+    Capabilities {
+        offset: u16,
+    },
+    // Part of the message set, but no backend needs it yet.
+    #[allow(dead_code)]
+    TimingReport,
+}
+
+impl Request {
+    /// Encodes the request into the bytes to write to `I2C_ADDR`.
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = match *self {
+            Request::GetVcp { code } => vec![GET_VCP_REQUEST, code],
+            Request::SetVcp { code, value } => {
+                let [hi, lo] = value.to_be_bytes();
+                vec![SET_VCP_REQUEST, code, hi, lo]
+            }
+            Request::Capabilities { offset } => {
+                let [hi, lo] = offset.to_be_bytes();
+                vec![CAPABILITIES_REQUEST, hi, lo]
+            }
+            Request::TimingReport => vec![TIMING_REQUEST],
+        };
+
+        let mut packet = Vec::with_capacity(payload.len() + 3);
+        packet.push(HOST_ADDR);
+        packet.push(0x80 | payload.len() as u8);
+        packet.extend_from_slice(&payload);
+        packet.push(checksum(DISPLAY_ADDR, &packet));
+        packet
+    }
+
+    /// The number of bytes to read back for the reply to this request, or 0
+    /// if it doesn't have one.
+    pub fn reply_len(&self) -> usize {
+        match self {
+            Request::GetVcp { .. } => 11,
+            Request::SetVcp { .. } => 0,
+            Request::Capabilities { .. } => MAX_REPLY_LEN,
+            Request::TimingReport => 9,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reply {
+    /// Sent by the display when it has nothing to report, e.g. because it
+    /// doesn't support the request or isn't ready to answer it yet.
+    Null,
+    Vcp(VcpReply),
+    Capabilities {
+        offset: u16,
+        data: Vec<u8>,
+    },
+    Timing(TimingReport),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VcpReply {
+    pub code: u8,
+    pub supported: bool,
+    /// 0 for a set parameter, `VCP_TYPE_MOMENTARY` for a momentary control.
+    pub kind: u8,
+    pub max: u16,
+    pub current: u16,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimingReport {
+    pub status: u8,
+    /// Horizontal frequency in units of 10Hz.
+    pub h_freq: u16,
+    /// Vertical frequency in units of 0.01Hz.
+    pub v_freq: u16,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,
+    BadSourceAddress(u8),
+    BadLength(u8),
+    Checksum { expected: u8, actual: u8 },
+    UnknownOpcode(u8),
+    BadPayload(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "truncated ddc/ci message"),
+            DecodeError::BadSourceAddress(addr) => {
+                write!(f, "unexpected ddc/ci source address 0x{:02x}", addr)
+            }
+            DecodeError::BadLength(len) => write!(f, "invalid ddc/ci length byte 0x{:02x}", len),
+            DecodeError::Checksum { expected, actual } => write!(
+                f,
+                "ddc/ci checksum mismatch (expected 0x{:02x}, got 0x{:02x})",
+                expected, actual
+            ),
+            DecodeError::UnknownOpcode(op) => write!(f, "unknown ddc/ci opcode 0x{:02x}", op),
+            DecodeError::BadPayload(op) => {
+                write!(f, "malformed payload for ddc/ci opcode 0x{:02x}", op)
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+impl Reply {
+    /// Decodes a reply read from `I2C_ADDR`. Any bytes after the checksum are
+    /// ignored, so the buffer may be larger than the reply.
+    pub fn decode(bytes: &[u8]) -> Result<Reply, DecodeError> {
+        if bytes.len() < 3 {
+            return Err(DecodeError::Truncated);
+        }
+
+        if bytes[0] != DISPLAY_ADDR {
+            return Err(DecodeError::BadSourceAddress(bytes[0]));
+        }
+
+        // The timing report is the only message that is sent without the
+        // high bit set in its length byte.
+        let len_byte = bytes[1];
+        let len = if len_byte & 0x80 != 0 {
+            usize::from(len_byte & 0x7f)
+        } else if len_byte == 6 && bytes.get(2) == Some(&TIMING_REPLY) {
+            6
+        } else {
+            return Err(DecodeError::BadLength(len_byte));
+        };
+
+        if bytes.len() < len + 3 {
+            return Err(DecodeError::Truncated);
+        }
+
+        let expected = checksum(HOST_READ_ADDR, &bytes[..len + 2]);
+        let actual = bytes[len + 2];
+        if expected != actual {
+            return Err(DecodeError::Checksum { expected, actual });
+        }
+
+        let payload = &bytes[2..len + 2];
+        let (&opcode, args) = match payload.split_first() {
+            Some(v) => v,
+            None => return Ok(Reply::Null),
+        };
+
+        match opcode {
+            GET_VCP_REPLY => {
+                if args.len() != 7 {
+                    return Err(DecodeError::BadPayload(opcode));
+                }
+                Ok(Reply::Vcp(VcpReply {
+                    supported: args[0] == 0,
+                    code: args[1],
+                    kind: args[2],
+                    max: u16::from_be_bytes([args[3], args[4]]),
+                    current: u16::from_be_bytes([args[5], args[6]]),
+                }))
+            }
+            CAPABILITIES_REPLY => {
+                if args.len() < 2 {
+                    return Err(DecodeError::BadPayload(opcode));
+                }
+                Ok(Reply::Capabilities {
+                    offset: u16::from_be_bytes([args[0], args[1]]),
+                    data: args[2..].to_vec(),
+                })
+            }
+            TIMING_REPLY => {
+                if args.len() != 5 {
+                    return Err(DecodeError::BadPayload(opcode));
+                }
+                Ok(Reply::Timing(TimingReport {
+                    status: args[0],
+                    h_freq: u16::from_be_bytes([args[1], args[2]]),
+                    v_freq: u16::from_be_bytes([args[3], args[4]]),
+                }))
+            }
+            _ => Err(DecodeError::UnknownOpcode(opcode)),
+        }
+    }
+}
+
+fn checksum(init: u8, bytes: &[u8]) -> u8 {
+    bytes.iter().fold(init, |acc, b| acc ^ b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames a reply payload the way a display sends it.
+    fn reply(payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![DISPLAY_ADDR, 0x80 | payload.len() as u8];
+        bytes.extend_from_slice(payload);
+        bytes.push(checksum(HOST_READ_ADDR, &bytes));
+        bytes
+    }
+
+    #[test]
+    fn encodes_get_vcp() {
+        // The example from the DDC/CI spec, which includes the display's
+        // write address (0x6e) in front.
+        assert_eq!(
+            Request::GetVcp { code: 0x10 }.encode(),
+            [0x51, 0x82, 0x01, 0x10, 0xac]
+        );
+    }
+
+    #[test]
+    fn encodes_set_vcp() {
+        let packet = Request::SetVcp {
+            code: 0x10,
+            value: 0x0132,
+        }
+        .encode();
+
+        assert_eq!(packet[..6], [0x51, 0x84, 0x03, 0x10, 0x01, 0x32]);
+        assert_eq!(checksum(DISPLAY_ADDR, &packet), 0);
+    }
+
+    #[test]
+    fn encodes_capabilities_and_timing_requests() {
+        let packet = Request::Capabilities { offset: 0x0120 }.encode();
+        assert_eq!(packet[..5], [0x51, 0x83, 0xf3, 0x01, 0x20]);
+        assert_eq!(checksum(DISPLAY_ADDR, &packet), 0);
+
+        let packet = Request::TimingReport.encode();
+        assert_eq!(packet[..3], [0x51, 0x81, 0x07]);
+        assert_eq!(checksum(DISPLAY_ADDR, &packet), 0);
+    }
+
+    #[test]
+    fn decodes_vcp_reply() {
+        let bytes = reply(&[GET_VCP_REPLY, 0x00, 0x10, 0x00, 0x00, 0x64, 0x00, 0x32]);
+
+        assert_eq!(
+            Reply::decode(&bytes),
+            Ok(Reply::Vcp(VcpReply {
+                code: 0x10,
+                supported: true,
+                kind: 0,
+                max: 100,
+                current: 50,
+            }))
+        );
+    }
+
+    #[test]
+    fn decodes_unsupported_vcp_reply() {
+        let bytes = reply(&[GET_VCP_REPLY, 0x01, 0xdf, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        match Reply::decode(&bytes) {
+            Ok(Reply::Vcp(reply)) => assert!(!reply.supported),
+            other => panic!("unexpected reply: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decodes_capabilities_fragments() {
+        let mut payload = vec![CAPABILITIES_REPLY, 0x00, 0x20];
+        payload.extend_from_slice(&[b'v'; MAX_CAPABILITIES_FRAGMENT]);
+        let bytes = reply(&payload);
+        assert_eq!(bytes.len(), MAX_REPLY_LEN);
+
+        assert_eq!(
+            Reply::decode(&bytes),
+            Ok(Reply::Capabilities {
+                offset: 0x20,
+                data: vec![b'v'; MAX_CAPABILITIES_FRAGMENT],
+            })
+        );
+
+        // The last fragment is empty.
+        assert_eq!(
+            Reply::decode(&reply(&[CAPABILITIES_REPLY, 0x01, 0x00])),
+            Ok(Reply::Capabilities {
+                offset: 0x100,
+                data: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn decodes_timing_report() {
+        // Sent with a plain length byte, unlike every other reply.
+        let mut bytes = vec![
+            DISPLAY_ADDR,
+            0x06,
+            TIMING_REPLY,
+            0x02,
+            0x1a,
+            0x5e,
+            0x17,
+            0x70,
+        ];
+        bytes.push(checksum(HOST_READ_ADDR, &bytes));
+
+        assert_eq!(
+            Reply::decode(&bytes),
+            Ok(Reply::Timing(TimingReport {
+                status: 0x02,
+                h_freq: 0x1a5e,
+                v_freq: 0x1770,
+            }))
+        );
+    }
+
+    #[test]
+    fn decodes_null_reply() {
+        assert_eq!(Reply::decode(&[0x6e, 0x80, 0xbe]), Ok(Reply::Null));
+    }
+
+    #[test]
+    fn ignores_trailing_bytes() {
+        let mut bytes = reply(&[]);
+        bytes.extend_from_slice(&[0xff; 8]);
+
+        assert_eq!(Reply::decode(&bytes), Ok(Reply::Null));
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut bytes = reply(&[GET_VCP_REPLY, 0x00, 0x10, 0x00, 0x00, 0x64, 0x00, 0x32]);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+
+        assert!(matches!(
+            Reply::decode(&bytes),
+            Err(DecodeError::Checksum { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_length() {
+        assert_eq!(
+            Reply::decode(&[0x6e, 0x05, 0x02, 0x00, 0x00]),
+            Err(DecodeError::BadLength(0x05))
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_replies() {
+        let bytes = reply(&[GET_VCP_REPLY, 0x00, 0x10, 0x00, 0x00, 0x64, 0x00, 0x32]);
+
+        for len in 0..bytes.len() {
+            assert_eq!(
+                Reply::decode(&bytes[..len]),
+                Err(DecodeError::Truncated),
+                "length {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_payloads() {
+        assert_eq!(
+            Reply::decode(&reply(&[GET_VCP_REPLY, 0x00, 0x10])),
+            Err(DecodeError::BadPayload(GET_VCP_REPLY))
+        );
+        assert_eq!(
+            Reply::decode(&reply(&[CAPABILITIES_REPLY, 0x00])),
+            Err(DecodeError::BadPayload(CAPABILITIES_REPLY))
+        );
+        assert_eq!(
+            Reply::decode(&reply(&[0x99])),
+            Err(DecodeError::UnknownOpcode(0x99))
+        );
+        assert_eq!(
+            Reply::decode(&[0x51, 0x80, 0xd1]),
+            Err(DecodeError::BadSourceAddress(0x51))
+        );
+    }
+}
@@ -0,0 +1,569 @@
+//! Decoding of EDID (Extended Display Identification Data), the block of
+//! identification data every display makes available to the host.
+
+use serde::Serialize;
+
+use super::MonitorError;
+
+const BLOCK_LEN: usize = 128;
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+const DESCRIPTOR_SERIAL: u8 = 0xff;
+const DESCRIPTOR_TEXT: u8 = 0xfe;
+const DESCRIPTOR_NAME: u8 = 0xfc;
+
+const EXTENSION_CTA: u8 = 0x02;
+const EXTENSION_DISPLAY_ID: u8 = 0x70;
+const EXTENSION_BLOCK_MAP: u8 = 0xf0;
+
+const CTA_AUDIO_BLOCK: u8 = 1;
+const CTA_VENDOR_BLOCK: u8 = 3;
+const CTA_EXTENDED_BLOCK: u8 = 7;
+const CTA_HDR_STATIC_METADATA: u8 = 0x06;
+
+const OUI_HDMI: u32 = 0x000c03;
+const OUI_HDMI_FORUM: u32 = 0xc45dd8;
+
+/// The decoded contents of a display's EDID.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Edid {
+    /// The three letter PNP id of the manufacturer, e.g. `DEL`.
+    pub manufacturer: String,
+    pub product: u16,
+    /// The numeric serial number, 0 if the display doesn't set one.
+    pub serial_number: u32,
+    /// The serial number from the display descriptors, which is usually the
+    /// one printed on the display.
+    pub serial_string: Option<String>,
+    /// The product name from the display descriptors.
+    pub name: Option<String>,
+    pub text: Option<String>,
+    /// The week of manufacture, if given.
+    pub week: Option<u8>,
+    /// The year of manufacture, or the model year if `model_year` is set.
+    pub year: u16,
+    pub model_year: bool,
+    /// The EDID version, e.g. `1.4`.
+    pub version: String,
+    pub digital: bool,
+    /// The physical size of the screen in centimetres, if given.
+    pub size_cm: Option<(u8, u8)>,
+    pub preferred_mode: Option<Mode>,
+    pub extensions: Vec<Extension>,
+}
+
+/// A video mode given by a detailed timing descriptor.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Mode {
+    pub width: u16,
+    pub height: u16,
+    pub refresh_hz: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum Extension {
+    /// A CTA-861 (formerly CEA-861) block, as sent by TVs and most monitors
+    /// with HDMI inputs.
+    Cta(CtaExtension),
+    DisplayId {
+        version: String,
+    },
+    BlockMap,
+    Unknown {
+        tag: u8,
+    },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CtaExtension {
+    pub revision: u8,
+    pub underscan: bool,
+    pub audio: bool,
+    pub ycbcr444: bool,
+    pub ycbcr422: bool,
+    pub hdmi: bool,
+    pub hdmi_forum: bool,
+    pub hdr: bool,
+}
+
+impl Edid {
+    /// Decodes an EDID base block, along with any extension blocks that
+    /// follow it. Extension blocks that fail validation are skipped, since
+    /// the base block holds everything needed to identify the display.
+    pub fn parse(bytes: &[u8]) -> Result<Edid, MonitorError> {
+        if bytes.len() < BLOCK_LEN {
+            return Err(MonitorError::Protocol("truncated edid".to_owned()));
+        }
+
+        let base = &bytes[..BLOCK_LEN];
+
+        if base[..8] != HEADER {
+            return Err(MonitorError::Protocol("invalid edid header".to_owned()));
+        }
+
+        if !checksum_ok(base) {
+            return Err(MonitorError::Checksum);
+        }
+
+        let id = u16::from_be_bytes([base[8], base[9]]);
+        let manufacturer = [(id >> 10) & 0x1f, (id >> 5) & 0x1f, id & 0x1f]
+            .iter()
+            .map(|&c| (b'A' - 1 + c as u8) as char)
+            .collect();
+
+        let mut edid = Edid {
+            manufacturer,
+            product: u16::from_le_bytes([base[10], base[11]]),
+            serial_number: u32::from_le_bytes([base[12], base[13], base[14], base[15]]),
+            serial_string: None,
+            name: None,
+            text: None,
+            week: match base[16] {
+                0 | 0xff => None,
+                week => Some(week),
+            },
+            year: 1990 + u16::from(base[17]),
+            model_year: base[16] == 0xff,
+            version: format!("{}.{}", base[18], base[19]),
+            digital: base[20] & 0x80 != 0,
+            size_cm: match (base[21], base[22]) {
+                (0, _) | (_, 0) => None,
+                size => Some(size),
+            },
+            preferred_mode: None,
+            extensions: vec![],
+        };
+
+        for descriptor in base[54..126].chunks(18) {
+            if descriptor[0] != 0 || descriptor[1] != 0 {
+                if edid.preferred_mode.is_none() {
+                    edid.preferred_mode = detailed_timing(descriptor);
+                }
+                continue;
+            }
+
+            match descriptor[3] {
+                DESCRIPTOR_SERIAL => edid.serial_string = descriptor_text(descriptor),
+                DESCRIPTOR_TEXT => edid.text = descriptor_text(descriptor),
+                DESCRIPTOR_NAME => edid.name = descriptor_text(descriptor),
+                _ => {}
+            }
+        }
+
+        let count = usize::from(base[126]);
+        for (i, block) in bytes[BLOCK_LEN..]
+            .chunks_exact(BLOCK_LEN)
+            .take(count)
+            .enumerate()
+        {
+            if !checksum_ok(block) {
+                log::debug!("skipping edid extension {}: checksum mismatch", i + 1);
+                continue;
+            }
+            edid.extensions.push(parse_extension(block));
+        }
+
+        Ok(edid)
+    }
+
+    /// The serial number that identifies this particular display, preferring
+    /// the descriptor string over the numeric one.
+    pub fn serial(&self) -> Option<String> {
+        match &self.serial_string {
+            Some(serial) => Some(serial.clone()),
+            None if self.serial_number != 0 => Some(self.serial_number.to_string()),
+            None => None,
+        }
+    }
+
+    /// The product name, falling back to the manufacturer id and product
+    /// code if the display doesn't give one.
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{} {:04X}", self.manufacturer, self.product),
+        }
+    }
+}
+
+fn checksum_ok(block: &[u8]) -> bool {
+    block.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) == 0
+}
+
+fn descriptor_text(descriptor: &[u8]) -> Option<String> {
+    let text = &descriptor[5..];
+    let len = text.iter().take_while(|&&c| c != 0x0a).count();
+    let text = String::from_utf8_lossy(&text[..len]).trim().to_owned();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+fn detailed_timing(descriptor: &[u8]) -> Option<Mode> {
+    let clock = u32::from(u16::from_le_bytes([descriptor[0], descriptor[1]])) * 10_000;
+    let width = u16::from(descriptor[2]) | (u16::from(descriptor[4] & 0xf0) << 4);
+    let h_blank = u16::from(descriptor[3]) | (u16::from(descriptor[4] & 0x0f) << 8);
+    let height = u16::from(descriptor[5]) | (u16::from(descriptor[7] & 0xf0) << 4);
+    let v_blank = u16::from(descriptor[6]) | (u16::from(descriptor[7] & 0x0f) << 8);
+
+    let total = u32::from(width + h_blank) * u32::from(height + v_blank);
+    if total == 0 {
+        return None;
+    }
+
+    Some(Mode {
+        width,
+        height,
+        refresh_hz: (clock + total / 2) / total,
+    })
+}
+
+fn parse_extension(block: &[u8]) -> Extension {
+    match block[0] {
+        EXTENSION_CTA => Extension::Cta(parse_cta(block)),
+        EXTENSION_DISPLAY_ID => Extension::DisplayId {
+            version: format!("{}.{}", block[1] >> 4, block[1] & 0x0f),
+        },
+        EXTENSION_BLOCK_MAP => Extension::BlockMap,
+        tag => Extension::Unknown { tag },
+    }
+}
+
+fn parse_cta(block: &[u8]) -> CtaExtension {
+    let mut cta = CtaExtension {
+        revision: block[1],
+        ..CtaExtension::default()
+    };
+
+    // Revision 1 blocks have neither the support flags nor data blocks.
+    if cta.revision < 2 {
+        return cta;
+    }
+
+    let flags = block[3];
+    cta.underscan = flags & 0x80 != 0;
+    cta.audio = flags & 0x40 != 0;
+    cta.ycbcr444 = flags & 0x20 != 0;
+    cta.ycbcr422 = flags & 0x10 != 0;
+
+    if cta.revision < 3 {
+        return cta;
+    }
+
+    // Data blocks run from byte 4 up to the first detailed timing descriptor.
+    let end = usize::from(block[2]).min(BLOCK_LEN - 1);
+    let mut pos = 4;
+
+    while pos < end {
+        let tag = block[pos] >> 5;
+        let len = usize::from(block[pos] & 0x1f);
+        let data = match block.get(pos + 1..pos + 1 + len) {
+            Some(data) if pos + 1 + len <= end => data,
+            _ => break,
+        };
+
+        match tag {
+            CTA_AUDIO_BLOCK => cta.audio = true,
+            CTA_VENDOR_BLOCK if data.len() >= 3 => {
+                match u32::from_le_bytes([data[0], data[1], data[2], 0]) {
+                    OUI_HDMI => cta.hdmi = true,
+                    OUI_HDMI_FORUM => cta.hdmi_forum = true,
+                    _ => {}
+                }
+            }
+            CTA_EXTENDED_BLOCK if data.first() == Some(&CTA_HDR_STATIC_METADATA) => cta.hdr = true,
+            _ => {}
+        }
+
+        pos += 1 + len;
+    }
+
+    cta
+}
+
+/// Builds a base block with the given identification, for tests.
+#[cfg(test)]
+pub(super) fn build(
+    manufacturer: &str,
+    product: u16,
+    serial: Option<&str>,
+    name: Option<&str>,
+) -> Vec<u8> {
+    let mut block = vec![0; BLOCK_LEN];
+    block[..8].copy_from_slice(&HEADER);
+
+    let id = manufacturer
+        .bytes()
+        .fold(0u16, |id, c| (id << 5) | u16::from(c - b'A' + 1));
+    block[8..10].copy_from_slice(&id.to_be_bytes());
+    block[10..12].copy_from_slice(&product.to_le_bytes());
+    block[16] = 10;
+    block[17] = 30;
+    block[18] = 1;
+    block[19] = 4;
+    block[20] = 0x80;
+
+    let descriptors = [(DESCRIPTOR_SERIAL, serial), (DESCRIPTOR_NAME, name)];
+    for (i, (tag, text)) in descriptors.iter().enumerate() {
+        if let Some(text) = text {
+            let descriptor = &mut block[72 + i * 18..90 + i * 18];
+            descriptor[3] = *tag;
+            let mut bytes = text.as_bytes().to_vec();
+            bytes.push(0x0a);
+            bytes.resize(13, b' ');
+            descriptor[5..].copy_from_slice(&bytes[..13]);
+        }
+    }
+
+    block[127] = 0u8.wrapping_sub(block.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)));
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fix_checksum(block: &mut [u8]) {
+        block[127] = 0;
+        block[127] = 0u8.wrapping_sub(block.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)));
+    }
+
+    fn dell() -> Vec<u8> {
+        build("DEL", 0xa0b8, Some("ABC123"), Some("DELL U2720Q"))
+    }
+
+    /// A base block followed by the given extensions.
+    fn with_extensions(extensions: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = dell();
+        bytes[126] = extensions.len() as u8;
+        fix_checksum(&mut bytes);
+        for extension in extensions {
+            bytes.extend_from_slice(extension);
+        }
+        bytes
+    }
+
+    fn cta(revision: u8, flags: u8, data_blocks: &[&[u8]]) -> Vec<u8> {
+        let mut block = vec![0; BLOCK_LEN];
+        block[0] = EXTENSION_CTA;
+        block[1] = revision;
+        block[3] = flags;
+
+        let mut pos = 4;
+        for data_block in data_blocks {
+            block[pos..pos + data_block.len()].copy_from_slice(data_block);
+            pos += data_block.len();
+        }
+        block[2] = pos as u8;
+
+        fix_checksum(&mut block);
+        block
+    }
+
+    #[test]
+    fn parses_base_block() {
+        let edid = Edid::parse(&dell()).unwrap();
+
+        assert_eq!(edid.manufacturer, "DEL");
+        assert_eq!(edid.product, 0xa0b8);
+        assert_eq!(edid.serial_number, 0);
+        assert_eq!(edid.serial_string.as_deref(), Some("ABC123"));
+        assert_eq!(edid.name.as_deref(), Some("DELL U2720Q"));
+        assert_eq!(edid.text, None);
+        assert_eq!(edid.week, Some(10));
+        assert_eq!(edid.year, 2020);
+        assert!(!edid.model_year);
+        assert_eq!(edid.version, "1.4");
+        assert!(edid.digital);
+        assert_eq!(edid.size_cm, None);
+        assert_eq!(edid.preferred_mode, None);
+        assert!(edid.extensions.is_empty());
+
+        assert_eq!(edid.serial().as_deref(), Some("ABC123"));
+        assert_eq!(edid.display_name(), "DELL U2720Q");
+    }
+
+    #[test]
+    fn parses_descriptor_strings() {
+        // Strings end at a line feed, and are padded with spaces after it.
+        let mut bytes = build("GSM", 0x5b09, Some("  12345"), None);
+        let text = &mut bytes[108..126];
+        text[3] = DESCRIPTOR_TEXT;
+        text[5..].copy_from_slice(b"HDR\nignored  ");
+        bytes[12..16].copy_from_slice(&7u32.to_le_bytes());
+        fix_checksum(&mut bytes);
+
+        let edid = Edid::parse(&bytes).unwrap();
+
+        assert_eq!(edid.serial_string.as_deref(), Some("12345"));
+        assert_eq!(edid.text.as_deref(), Some("HDR"));
+        assert_eq!(edid.name, None);
+        assert_eq!(edid.display_name(), "GSM 5B09");
+        assert_eq!(edid.serial().as_deref(), Some("12345"));
+    }
+
+    #[test]
+    fn falls_back_to_numeric_serial() {
+        let mut bytes = build("GSM", 0x5b09, Some(" "), None);
+        assert_eq!(Edid::parse(&bytes).unwrap().serial(), None);
+
+        bytes[12..16].copy_from_slice(&123_456u32.to_le_bytes());
+        fix_checksum(&mut bytes);
+        let edid = Edid::parse(&bytes).unwrap();
+
+        assert_eq!(edid.serial_string, None);
+        assert_eq!(edid.serial().as_deref(), Some("123456"));
+    }
+
+    #[test]
+    fn parses_preferred_mode() {
+        let mut bytes = dell();
+        // 1920x1080 at 148.5 MHz, with 280 and 45 lines of blanking.
+        bytes[54..62].copy_from_slice(&[0x02, 0x3a, 0x80, 0x18, 0x71, 0x38, 0x2d, 0x40]);
+        bytes[21] = 60;
+        bytes[22] = 34;
+        fix_checksum(&mut bytes);
+
+        let edid = Edid::parse(&bytes).unwrap();
+
+        assert_eq!(
+            edid.preferred_mode,
+            Some(Mode {
+                width: 1920,
+                height: 1080,
+                refresh_hz: 60,
+            })
+        );
+        assert_eq!(edid.size_cm, Some((60, 34)));
+    }
+
+    #[test]
+    fn rejects_invalid_blocks() {
+        let bytes = dell();
+
+        assert!(matches!(
+            Edid::parse(&bytes[..BLOCK_LEN - 1]),
+            Err(MonitorError::Protocol(_))
+        ));
+
+        let mut header = bytes.clone();
+        header[0] = 0xff;
+        fix_checksum(&mut header);
+        assert!(matches!(
+            Edid::parse(&header),
+            Err(MonitorError::Protocol(_))
+        ));
+
+        let mut checksum = bytes;
+        checksum[100] ^= 0x01;
+        assert!(matches!(
+            Edid::parse(&checksum),
+            Err(MonitorError::Checksum)
+        ));
+    }
+
+    #[test]
+    fn parses_cta_blocks() {
+        let bytes = with_extensions(&[cta(
+            3,
+            0xf0,
+            &[
+                // Audio, with one short audio descriptor.
+                &[0x23, 0x09, 0x07, 0x07],
+                // HDMI vendor specific block.
+                &[0x65, 0x03, 0x0c, 0x00, 0x10, 0x00],
+                // HDMI Forum vendor specific block.
+                &[0x63, 0xd8, 0x5d, 0xc4],
+                // HDR static metadata.
+                &[0xe3, 0x06, 0x05, 0x01],
+            ],
+        )]);
+
+        let edid = Edid::parse(&bytes).unwrap();
+
+        assert_eq!(
+            edid.extensions,
+            [Extension::Cta(CtaExtension {
+                revision: 3,
+                underscan: true,
+                audio: true,
+                ycbcr444: true,
+                ycbcr422: true,
+                hdmi: true,
+                hdmi_forum: true,
+                hdr: true,
+            })]
+        );
+    }
+
+    #[test]
+    fn parses_cta_blocks_by_revision() {
+        let bytes = with_extensions(&[
+            // Revision 1 has no flags, and revision 2 no data blocks.
+            cta(1, 0xf0, &[]),
+            cta(2, 0x40, &[&[0x65, 0x03, 0x0c, 0x00, 0x10, 0x00]]),
+            // Data blocks that run past the end of the collection are ignored.
+            {
+                let mut block = cta(3, 0, &[&[0x65, 0x03, 0x0c, 0x00, 0x10, 0x00]]);
+                block[2] = 8;
+                fix_checksum(&mut block);
+                block
+            },
+        ]);
+
+        let edid = Edid::parse(&bytes).unwrap();
+
+        assert_eq!(
+            edid.extensions,
+            [
+                Extension::Cta(CtaExtension {
+                    revision: 1,
+                    ..CtaExtension::default()
+                }),
+                Extension::Cta(CtaExtension {
+                    revision: 2,
+                    audio: true,
+                    ..CtaExtension::default()
+                }),
+                Extension::Cta(CtaExtension {
+                    revision: 3,
+                    ..CtaExtension::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_invalid_extensions() {
+        let mut bad = cta(3, 0xf0, &[]);
+        bad[3] = 0;
+
+        let mut display_id = vec![0; BLOCK_LEN];
+        display_id[0] = EXTENSION_DISPLAY_ID;
+        display_id[1] = 0x12;
+        fix_checksum(&mut display_id);
+
+        let mut unknown = vec![0; BLOCK_LEN];
+        unknown[0] = 0x40;
+        fix_checksum(&mut unknown);
+
+        let mut bytes = with_extensions(&[bad, display_id, unknown]);
+        // Blocks beyond the count in the base block are ignored.
+        bytes.extend_from_slice(&cta(3, 0xf0, &[]));
+
+        let edid = Edid::parse(&bytes).unwrap();
+
+        assert_eq!(
+            edid.extensions,
+            [
+                Extension::DisplayId {
+                    version: "1.2".to_owned()
+                },
+                Extension::Unknown { tag: 0x40 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use super::ddc::DecodeError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MonitorError {
+    /// The monitor or device being looked for doesn't exist (anymore).
+    NotFound(String),
+    /// The monitor doesn't respond to DDC/CI at all.
+    DdcUnsupported,
+    /// The monitor doesn't support the given VCP feature.
+    UnsupportedFeature(u8),
+    /// A value was rejected before being sent to the monitor.
+    InvalidValue(String),
+    /// The monitor didn't answer in time.
+    Timeout,
+    /// A reply from the monitor failed checksum validation.
+    Checksum,
+    /// The monitor sent something that couldn't be understood.
+    Protocol(String),
+    /// A call into the operating system failed.
+    Os { code: i32, message: String },
+}
+
+impl MonitorError {
+    /// Whether the operation might succeed if it is tried again.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            MonitorError::Timeout | MonitorError::Checksum | MonitorError::Protocol(_)
+        )
+    }
+}
+
+impl fmt::Display for MonitorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MonitorError::NotFound(what) => write!(f, "not found: {}", what),
+            MonitorError::DdcUnsupported => write!(f, "monitor does not support ddc/ci"),
+            MonitorError::UnsupportedFeature(code) => {
+                write!(f, "unsupported vcp feature 0x{:02x}", code)
+            }
+            MonitorError::InvalidValue(message) => write!(f, "invalid value: {}", message),
+            MonitorError::Timeout => write!(f, "timed out waiting for the monitor"),
+            MonitorError::Checksum => write!(f, "ddc/ci checksum mismatch"),
+            MonitorError::Protocol(message) => write!(f, "ddc/ci protocol error: {}", message),
+            MonitorError::Os { code, message } => write!(f, "{} (os error {})", message, code),
+        }
+    }
+}
+
+impl Error for MonitorError {}
+
+impl From<io::Error> for MonitorError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => MonitorError::Timeout,
+            _ => {
+                let code = e.raw_os_error().unwrap_or(-1);
+                let message = e.to_string();
+                let message = message.trim_end_matches(&format!(" (os error {})", code));
+                MonitorError::Os {
+                    code,
+                    message: message.to_owned(),
+                }
+            }
+        }
+    }
+}
+
+impl From<DecodeError> for MonitorError {
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::Checksum { .. } => MonitorError::Checksum,
+            e => MonitorError::Protocol(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer};
+
+/// Input source names and their VCP 0x60 values, as defined by MCCS.
+const INPUT_SOURCES: &[(&str, u8)] = &[
+    ("vga1", 0x01),
+    ("vga2", 0x02),
+    ("dvi1", 0x03),
+    ("dvi2", 0x04),
+    ("composite1", 0x05),
+    ("composite2", 0x06),
+    ("svideo1", 0x07),
+    ("svideo2", 0x08),
+    ("tuner1", 0x09),
+    ("tuner2", 0x0a),
+    ("tuner3", 0x0b),
+    ("component1", 0x0c),
+    ("component2", 0x0d),
+    ("component3", 0x0e),
+    ("dp1", 0x0f),
+    ("dp2", 0x10),
+    ("hdmi1", 0x11),
+    ("hdmi2", 0x12),
+    // Not part of MCCS, but the value used by most monitors with USB-C input.
+    ("usb-c", 0x1b),
+];
+
+/// A value of the input select VCP feature (0x60).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InputSource(pub u8);
+
+impl InputSource {
+    pub fn name(&self) -> Option<&'static str> {
+        INPUT_SOURCES
+            .iter()
+            .find(|(_, value)| *value == self.0)
+            .map(|(name, _)| *name)
+    }
+}
+
+impl fmt::Display for InputSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "0x{:02x}", self.0),
+        }
+    }
+}
+
+impl FromStr for InputSource {
+    type Err = Box<dyn Error>;
+
+    /// Parses either an input source name or a raw value.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_lowercase();
+        if let Some((_, value)) = INPUT_SOURCES.iter().find(|(n, _)| *n == name) {
+            return Ok(InputSource(*value));
+        }
+
+        super::parse_vcp_code(s)
+            .map(InputSource)
+            .map_err(|_| format!("unknown input source: {}", s).into())
+    }
+}
+
+/// Deserializes from either a raw value or a string in any of the forms
+/// accepted by `from_str`.
+impl<'de> Deserialize<'de> for InputSource {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u8),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(value) => Ok(InputSource(value)),
+            Raw::Text(s) => s.parse().map_err(de::Error::custom),
+        }
+    }
+}
@@ -0,0 +1,720 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use super::ddc::{self, Reply, Request};
+use super::{DisplayBackend, Edid, Handle, MonitorError, MonitorInfo, VcpType, VcpValue};
+
+const EDID_ADDR: u16 = 0x50;
+const EDID_BLOCK_LEN: usize = 128;
+
+// Delays the display needs to process a request before it will respond
+// to the next transaction, as given by the DDC/CI spec.
+const REPLY_DELAY: Duration = Duration::from_millis(40);
+const WRITE_DELAY: Duration = Duration::from_millis(50);
+
+const RETRIES: usize = 3;
+
+// Guards against displays that never send the empty fragment marking the end
+// of their capabilities string.
+const MAX_CAPABILITIES_LEN: usize = 4096;
+
+const I2C_SLAVE: libc::c_ulong = 0x0703;
+
+// The PCI class of display controllers, in the top byte of the class code.
+const PCI_CLASS_DISPLAY: u32 = 0x03;
+
+/// A single I2C bus, addressed one slave at a time.
+pub trait I2cBus: Send {
+    fn write(&mut self, addr: u16, data: &[u8]) -> io::Result<()>;
+    fn read(&mut self, addr: u16, buf: &mut [u8]) -> io::Result<()>;
+}
+
+/// Finds the I2C buses that monitors might be attached to.
+pub trait I2cProvider: Send + Sync {
+    /// Returns the available buses along with a name identifying each one.
+    fn open_buses(&self) -> Vec<(String, Box<dyn I2cBus>)>;
+
+    /// Returns the EDID of the display on the named bus if the system
+    /// already has a copy of it, which saves reading it over the bus.
+    fn edid(&self, _bus: &str) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Provides the buses exposed by the kernel's i2c-dev interface
+/// (`/dev/i2c-*`). Only buses that belong to a display are used, since
+/// reading an EDID means writing to the bus, and other devices such as the
+/// SPD EEPROMs on the SMBus also answer at 0x50.
+pub struct DevI2c;
+
+impl I2cProvider for DevI2c {
+    fn open_buses(&self) -> Vec<(String, Box<dyn I2cBus>)> {
+        let entries = match fs::read_dir("/dev") {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::error!("failed to read /dev: {}", e);
+                return vec![];
+            }
+        };
+
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("i2c-"))
+            })
+            .collect();
+
+        paths.sort();
+
+        let ddc_channels = ddc_channels();
+
+        paths
+            .into_iter()
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default();
+                let probe = ddc_channels.contains(name) || is_display_adapter(name);
+                if !probe {
+                    log::debug!("skipping {}: not a display bus", path.display());
+                }
+                probe
+            })
+            .filter_map(|path| {
+                let file = match OpenOptions::new().read(true).write(true).open(&path) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        log::debug!("failed to open {}: {}", path.display(), e);
+                        return None;
+                    }
+                };
+
+                let bus: Box<dyn I2cBus> = Box::new(DevI2cBus { file, slave: None });
+                Some((path.display().to_string(), bus))
+            })
+            .collect()
+    }
+
+    /// Looks for the DRM connector whose DDC channel is the bus, since the
+    /// kernel exposes the full EDID (including every extension block) there.
+    fn edid(&self, bus: &str) -> Option<Vec<u8>> {
+        let bus = Path::new(bus).file_name()?;
+
+        for entry in fs::read_dir("/sys/class/drm").ok()?.filter_map(|e| e.ok()) {
+            let ddc = match fs::read_link(entry.path().join("ddc")) {
+                Ok(ddc) => ddc,
+                Err(_) => continue,
+            };
+
+            if ddc.file_name() == Some(bus) {
+                return fs::read(entry.path().join("edid"))
+                    .ok()
+                    .filter(|edid| !edid.is_empty());
+            }
+        }
+
+        None
+    }
+}
+
+/// The buses that DRM connectors use as their DDC channel, e.g. `i2c-5`.
+fn ddc_channels() -> HashSet<OsString> {
+    let entries = match fs::read_dir("/sys/class/drm") {
+        Ok(entries) => entries,
+        Err(_) => return HashSet::new(),
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| fs::read_link(entry.path().join("ddc")).ok())
+        .filter_map(|ddc| ddc.file_name().map(OsStr::to_owned))
+        .collect()
+}
+
+/// Whether the bus is provided by a display controller. Drivers such as
+/// NVIDIA's don't link their connectors to their buses, so this is the only
+/// way to find those, while still leaving out SMBus and other controllers.
+fn is_display_adapter(bus: &OsStr) -> bool {
+    let class = Path::new("/sys/bus/i2c/devices")
+        .join(bus)
+        .join("device/class");
+
+    fs::read_to_string(class)
+        .ok()
+        .and_then(|class| u32::from_str_radix(class.trim().trim_start_matches("0x"), 16).ok())
+        .is_some_and(|class| class >> 16 == PCI_CLASS_DISPLAY)
+}
+
+struct DevI2cBus {
+    file: File,
+    slave: Option<u16>,
+}
+
+impl DevI2cBus {
+    fn select(&mut self, addr: u16) -> io::Result<()> {
+        if self.slave != Some(addr) {
+            let res =
+                unsafe { libc::ioctl(self.file.as_raw_fd(), I2C_SLAVE, addr as libc::c_ulong) };
+            if res < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.slave = Some(addr);
+        }
+        Ok(())
+    }
+}
+
+impl I2cBus for DevI2cBus {
+    fn write(&mut self, addr: u16, data: &[u8]) -> io::Result<()> {
+        self.select(addr)?;
+        self.file.write_all(data)
+    }
+
+    fn read(&mut self, addr: u16, buf: &mut [u8]) -> io::Result<()> {
+        self.select(addr)?;
+        self.file.read_exact(buf)
+    }
+}
+
+/// Controls monitors over DDC/CI on the I2C buses given by `P`.
+pub struct LinuxBackend<P> {
+    provider: P,
+    /// Buses by handle. A bus keeps its handle for good, so that handles
+    /// given out before an enumeration still point at the same bus after.
+    buses: Mutex<Vec<KnownBus>>,
+}
+
+struct KnownBus {
+    name: String,
+    /// `None` while there's no monitor on the bus.
+    bus: Option<Box<dyn I2cBus>>,
+}
+
+impl<P: I2cProvider> LinuxBackend<P> {
+    pub fn new(provider: P) -> LinuxBackend<P> {
+        LinuxBackend {
+            provider,
+            buses: Mutex::new(vec![]),
+        }
+    }
+
+    fn with_bus<T>(
+        &self,
+        handle: Handle,
+        f: impl Fn(&mut dyn I2cBus) -> Result<T, MonitorError>,
+    ) -> Result<T, MonitorError> {
+        let mut buses = self.buses.lock().unwrap();
+        let bus = buses
+            .get_mut(handle.0)
+            .and_then(|known| known.bus.as_mut())
+            .ok_or_else(|| MonitorError::NotFound(format!("i2c bus for handle {}", handle.0)))?;
+
+        let mut res = f(bus.as_mut());
+        for _ in 1..RETRIES {
+            match res {
+                Err(ref e) if e.is_transient() => {
+                    log::debug!("ddc/ci transaction failed, retrying: {}", e)
+                }
+                _ => break,
+            }
+            res = f(bus.as_mut());
+        }
+        res
+    }
+}
+
+impl<P: I2cProvider> DisplayBackend for LinuxBackend<P> {
+    fn enumerate(&self) -> Result<Vec<MonitorInfo>, MonitorError> {
+        let mut buses = self.buses.lock().unwrap();
+        let mut monitors = Vec::new();
+
+        for known in buses.iter_mut() {
+            known.bus = None;
+        }
+
+        for (name, mut bus) in self.provider.open_buses() {
+            let edid = match self.provider.edid(&name) {
+                Some(edid) => Edid::parse(&edid),
+                None => read_edid(bus.as_mut()).and_then(|edid| Edid::parse(&edid)),
+            };
+
+            let edid = match edid {
+                Ok(edid) => edid,
+                Err(e) => {
+                    log::debug!("no edid found on {}: {}", name, e);
+                    continue;
+                }
+            };
+
+            let handle = match buses.iter().position(|known| known.name == name) {
+                Some(handle) => handle,
+                None => {
+                    buses.push(KnownBus {
+                        name: name.clone(),
+                        bus: None,
+                    });
+                    buses.len() - 1
+                }
+            };
+            buses[handle].bus = Some(bus);
+
+            monitors.push(MonitorInfo {
+                name: edid.display_name(),
+                handle: Handle(handle),
+                edid: Some(edid),
+                path: name,
+            });
+        }
+
+        Ok(monitors)
+    }
+
+    fn get_vcp(&self, handle: Handle, code: u8) -> Result<VcpValue, MonitorError> {
+        self.with_bus(handle, |bus| {
+            match transact(bus, Request::GetVcp { code })? {
+                Reply::Vcp(reply) if reply.code == code => {
+                    if reply.supported {
+                        Ok(VcpValue {
+                            current: u32::from(reply.current),
+                            max: u32::from(reply.max),
+                            kind: if reply.kind == ddc::VCP_TYPE_MOMENTARY {
+                                VcpType::Momentary
+                            } else {
+                                VcpType::SetParameter
+                            },
+                        })
+                    } else {
+                        Err(MonitorError::UnsupportedFeature(code))
+                    }
+                }
+                _ => Err(MonitorError::Protocol(
+                    "unexpected reply to get vcp request".to_owned(),
+                )),
+            }
+        })
+    }
+
+    fn set_vcp(&self, handle: Handle, code: u8, value: u32) -> Result<(), MonitorError> {
+        let value = u16::try_from(value)
+            .map_err(|_| MonitorError::InvalidValue(format!("{} is out of range", value)))?;
+        self.with_bus(handle, |bus| {
+            transact(bus, Request::SetVcp { code, value })?;
+            Ok(())
+        })
+    }
+
+    fn capabilities(&self, handle: Handle) -> Result<String, MonitorError> {
+        let mut caps = Vec::new();
+
+        loop {
+            let offset = caps.len() as u16;
+            let fragment = self.with_bus(handle, |bus| {
+                match transact(bus, Request::Capabilities { offset })? {
+                    Reply::Capabilities { offset: o, data } if o == offset => Ok(data),
+                    _ => Err(MonitorError::Protocol(
+                        "unexpected reply to capabilities request".to_owned(),
+                    )),
+                }
+            })?;
+
+            if fragment.is_empty() {
+                break;
+            }
+
+            caps.extend_from_slice(&fragment);
+
+            if caps.len() > MAX_CAPABILITIES_LEN {
+                return Err(MonitorError::Protocol(
+                    "capabilities string is too long".to_owned(),
+                ));
+            }
+        }
+
+        let len = caps.iter().take_while(|&&c| c != 0).count();
+        Ok(String::from_utf8_lossy(&caps[..len]).into_owned())
+    }
+}
+
+/// Sends a request to the display and reads back its reply, if it has one.
+/// A null reply is treated as an error, since it means the display couldn't
+/// answer the request.
+fn transact(bus: &mut dyn I2cBus, request: Request) -> Result<Reply, MonitorError> {
+    bus.write(ddc::I2C_ADDR, &request.encode())
+        .map_err(i2c_error)?;
+
+    if request.reply_len() == 0 {
+        thread::sleep(WRITE_DELAY);
+        return Ok(Reply::Null);
+    }
+
+    thread::sleep(REPLY_DELAY);
+
+    let mut buf = vec![0; request.reply_len()];
+    bus.read(ddc::I2C_ADDR, &mut buf).map_err(i2c_error)?;
+
+    match Reply::decode(&buf)? {
+        Reply::Null => Err(MonitorError::Protocol(
+            "display sent a null reply".to_owned(),
+        )),
+        reply => Ok(reply),
+    }
+}
+
+/// Maps a failed I2C transfer to a `MonitorError`. A display that doesn't
+/// acknowledge its DDC/CI address doesn't speak the protocol at all.
+fn i2c_error(e: io::Error) -> MonitorError {
+    match e.raw_os_error() {
+        Some(libc::ENXIO) | Some(libc::EREMOTEIO) => MonitorError::DdcUnsupported,
+        _ => e.into(),
+    }
+}
+
+/// Reads the EDID base block and the first extension block, if there is
+/// one. Blocks past those are in later segments, which need a combined
+/// transaction that the i2c-dev read and write calls can't express.
+fn read_edid(bus: &mut dyn I2cBus) -> Result<Vec<u8>, MonitorError> {
+    let mut edid = vec![0; EDID_BLOCK_LEN];
+
+    bus.write(EDID_ADDR, &[0])?;
+    bus.read(EDID_ADDR, &mut edid)?;
+
+    if edid[126] > 0 {
+        let mut block = [0; EDID_BLOCK_LEN];
+        bus.write(EDID_ADDR, &[EDID_BLOCK_LEN as u8])?;
+        bus.read(EDID_ADDR, &mut block)?;
+        edid.extend_from_slice(&block);
+    }
+
+    Ok(edid)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::monitors::edid;
+
+    /// A display as seen from the bus, answering DDC/CI requests from its
+    /// VCP features and capabilities string.
+    #[derive(Default)]
+    struct Display {
+        edid: Vec<u8>,
+        edid_offset: usize,
+        vcp: HashMap<u8, (u16, u16)>,
+        caps: Vec<u8>,
+        reply: Vec<u8>,
+        /// How many replies to corrupt before answering properly.
+        corrupt: usize,
+        writes: Vec<(u16, Vec<u8>)>,
+    }
+
+    /// A bus with a scripted display on it, or nothing if `None`.
+    #[derive(Clone)]
+    struct FakeBus(Option<Arc<Mutex<Display>>>);
+
+    struct FakeProvider {
+        buses: Vec<(String, FakeBus)>,
+        sysfs: HashMap<String, Vec<u8>>,
+    }
+
+    impl Display {
+        fn respond(&mut self, request: &[u8]) -> Vec<u8> {
+            assert_eq!(request[0], 0x51, "bad source address");
+            let len = usize::from(request[1] & 0x7f);
+            assert_eq!(checksum(0x6e, request), 0, "bad checksum");
+
+            let payload = &request[2..2 + len];
+            let reply = match payload[0] {
+                0x01 => match self.vcp.get(&payload[1]) {
+                    Some(&(current, max)) => {
+                        let [max_hi, max_lo] = max.to_be_bytes();
+                        let [cur_hi, cur_lo] = current.to_be_bytes();
+                        vec![0x02, 0, payload[1], 0, max_hi, max_lo, cur_hi, cur_lo]
+                    }
+                    None => vec![0x02, 1, payload[1], 0, 0, 0, 0, 0],
+                },
+                0x03 => {
+                    let value = u16::from_be_bytes([payload[2], payload[3]]);
+                    if let Some(feature) = self.vcp.get_mut(&payload[1]) {
+                        feature.0 = value;
+                    }
+                    return vec![];
+                }
+                0xf3 => {
+                    let offset = usize::from(u16::from_be_bytes([payload[1], payload[2]]));
+                    let end = (offset + 32).min(self.caps.len());
+                    let mut reply = vec![0xe3, payload[1], payload[2]];
+                    reply.extend_from_slice(&self.caps[offset.min(end)..end]);
+                    reply
+                }
+                opcode => panic!("unexpected opcode 0x{:02x}", opcode),
+            };
+
+            let mut bytes = vec![0x6e, 0x80 | reply.len() as u8];
+            bytes.extend_from_slice(&reply);
+            bytes.push(checksum(0x50, &bytes));
+            bytes
+        }
+    }
+
+    impl I2cBus for FakeBus {
+        fn write(&mut self, addr: u16, data: &[u8]) -> io::Result<()> {
+            let display = self.0.as_ref().ok_or_else(no_device)?;
+            let mut display = display.lock().unwrap();
+            display.writes.push((addr, data.to_vec()));
+
+            match addr {
+                EDID_ADDR if !display.edid.is_empty() => {
+                    display.edid_offset = usize::from(data[0]);
+                    Ok(())
+                }
+                ddc::I2C_ADDR => {
+                    display.reply = display.respond(data);
+                    Ok(())
+                }
+                _ => Err(no_device()),
+            }
+        }
+
+        fn read(&mut self, addr: u16, buf: &mut [u8]) -> io::Result<()> {
+            let display = self.0.as_ref().ok_or_else(no_device)?;
+            let mut display = display.lock().unwrap();
+
+            match addr {
+                EDID_ADDR if !display.edid.is_empty() => {
+                    let offset = display.edid_offset;
+                    buf.copy_from_slice(&display.edid[offset..offset + buf.len()]);
+                    Ok(())
+                }
+                ddc::I2C_ADDR => {
+                    let mut reply = display.reply.clone();
+                    if display.corrupt > 0 {
+                        display.corrupt -= 1;
+                        if let Some(last) = reply.last_mut() {
+                            *last ^= 0xff;
+                        }
+                    }
+                    reply.resize(buf.len(), 0);
+                    buf.copy_from_slice(&reply);
+                    Ok(())
+                }
+                _ => Err(no_device()),
+            }
+        }
+    }
+
+    impl I2cProvider for FakeProvider {
+        fn open_buses(&self) -> Vec<(String, Box<dyn I2cBus>)> {
+            self.buses
+                .iter()
+                .map(|(name, bus)| (name.clone(), Box::new(bus.clone()) as Box<dyn I2cBus>))
+                .collect()
+        }
+
+        fn edid(&self, bus: &str) -> Option<Vec<u8>> {
+            self.sysfs.get(bus).cloned()
+        }
+    }
+
+    fn no_device() -> io::Error {
+        io::Error::from_raw_os_error(libc::ENXIO)
+    }
+
+    fn checksum(init: u8, bytes: &[u8]) -> u8 {
+        bytes.iter().fold(init, |acc, b| acc ^ b)
+    }
+
+    fn display(mut display: Display) -> Arc<Mutex<Display>> {
+        if display.edid.is_empty() {
+            display.edid = edid::build("DEL", 0xa0b8, Some("ABC123"), Some("DELL U2720Q"));
+        }
+        Arc::new(Mutex::new(display))
+    }
+
+    fn backend(displays: &[&Arc<Mutex<Display>>]) -> LinuxBackend<FakeProvider> {
+        let buses = displays
+            .iter()
+            .enumerate()
+            .map(|(i, display)| (format!("/dev/i2c-{}", i), FakeBus(Some((*display).clone()))))
+            .collect();
+
+        let backend = LinuxBackend::new(FakeProvider {
+            buses,
+            sysfs: HashMap::new(),
+        });
+        backend.enumerate().unwrap();
+        backend
+    }
+
+    #[test]
+    fn enumerates_displays_by_edid() {
+        let over_bus = display(Display {
+            edid: edid::build("DEL", 0xa0b8, Some("ABC123"), Some("DELL U2720Q")),
+            ..Display::default()
+        });
+        // The kernel's copy is used instead of reading it over the bus.
+        let from_sysfs = Arc::new(Mutex::new(Display::default()));
+
+        let backend = LinuxBackend::new(FakeProvider {
+            buses: vec![
+                ("/dev/i2c-0".to_owned(), FakeBus(None)),
+                ("/dev/i2c-1".to_owned(), FakeBus(Some(over_bus.clone()))),
+                ("/dev/i2c-2".to_owned(), FakeBus(Some(from_sysfs.clone()))),
+            ],
+            sysfs: vec![(
+                "/dev/i2c-2".to_owned(),
+                edid::build("GSM", 0x5b09, None, Some("LG ULTRAFINE")),
+            )]
+            .into_iter()
+            .collect(),
+        });
+
+        let monitors = backend.enumerate().unwrap();
+
+        assert_eq!(monitors.len(), 2);
+        assert_eq!(monitors[0].name, "DELL U2720Q");
+        assert_eq!(monitors[0].path, "/dev/i2c-1");
+        assert_eq!(monitors[0].handle, Handle(0));
+        assert_eq!(
+            monitors[0].edid.as_ref().unwrap().serial().as_deref(),
+            Some("ABC123")
+        );
+        assert_eq!(monitors[1].name, "LG ULTRAFINE");
+        assert_eq!(monitors[1].path, "/dev/i2c-2");
+        assert_eq!(monitors[1].handle, Handle(1));
+
+        assert!(from_sysfs.lock().unwrap().writes.is_empty());
+    }
+
+    #[test]
+    fn gets_vcp_features() {
+        let dell = display(Display {
+            vcp: vec![(0x10, (50, 100))].into_iter().collect(),
+            ..Display::default()
+        });
+        let backend = backend(&[&dell]);
+
+        let value = backend.get_vcp(Handle(0), 0x10).unwrap();
+        assert_eq!((value.current, value.max), (50, 100));
+        assert_eq!(value.kind, VcpType::SetParameter);
+
+        assert_eq!(
+            backend.get_vcp(Handle(0), 0x12).unwrap_err(),
+            MonitorError::UnsupportedFeature(0x12)
+        );
+        assert!(matches!(
+            backend.get_vcp(Handle(1), 0x10),
+            Err(MonitorError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn sets_vcp_features() {
+        let dell = display(Display {
+            vcp: vec![(0x10, (50, 100))].into_iter().collect(),
+            ..Display::default()
+        });
+        let backend = backend(&[&dell]);
+
+        backend.set_vcp(Handle(0), 0x10, 30).unwrap();
+        assert_eq!(dell.lock().unwrap().vcp[&0x10], (30, 100));
+        assert_eq!(backend.get_vcp(Handle(0), 0x10).unwrap().current, 30);
+
+        assert!(matches!(
+            backend.set_vcp(Handle(0), 0x10, 70000),
+            Err(MonitorError::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn reads_capabilities_in_fragments() {
+        let caps = "(prot(monitor)type(LCD)model(U2720Q)cmds(01 02 03 07 0C E3 F3)vcp(02 04 05 08 10 12 14(05 08 0B) 16 18 1A 60(0F 11 1B) D6(01 04 05))mccs_ver(2.1))";
+        let dell = display(Display {
+            caps: caps.as_bytes().to_vec(),
+            ..Display::default()
+        });
+        let backend = backend(&[&dell]);
+
+        assert_eq!(backend.capabilities(Handle(0)).unwrap(), caps);
+
+        let offsets: Vec<_> = dell
+            .lock()
+            .unwrap()
+            .writes
+            .iter()
+            .filter(|(addr, _)| *addr == ddc::I2C_ADDR)
+            .map(|(_, request)| u16::from_be_bytes([request[3], request[4]]))
+            .collect();
+        // The last request is answered with an empty fragment.
+        let mut expected: Vec<_> = (0..caps.len() as u16).step_by(32).collect();
+        expected.push(caps.len() as u16);
+        assert_eq!(offsets, expected);
+    }
+
+    #[test]
+    fn retries_transient_errors() {
+        let dell = display(Display {
+            vcp: vec![(0x10, (50, 100))].into_iter().collect(),
+            corrupt: RETRIES - 1,
+            ..Display::default()
+        });
+        let backend = backend(&[&dell]);
+
+        assert_eq!(backend.get_vcp(Handle(0), 0x10).unwrap().current, 50);
+        let requests = dell
+            .lock()
+            .unwrap()
+            .writes
+            .iter()
+            .filter(|(addr, _)| *addr == ddc::I2C_ADDR)
+            .count();
+        assert_eq!(requests, RETRIES);
+
+        dell.lock().unwrap().corrupt = RETRIES;
+        assert_eq!(
+            backend.get_vcp(Handle(0), 0x10).unwrap_err(),
+            MonitorError::Checksum
+        );
+    }
+
+    #[test]
+    fn keeps_handles_across_enumerations() {
+        let first = display(Display {
+            vcp: vec![(0x10, (10, 100))].into_iter().collect(),
+            ..Display::default()
+        });
+        let second = display(Display {
+            vcp: vec![(0x10, (20, 100))].into_iter().collect(),
+            ..Display::default()
+        });
+        let backend = backend(&[&first, &second]);
+
+        // Unplugging the first monitor leaves the second where it was.
+        let edid = std::mem::take(&mut first.lock().unwrap().edid);
+        let monitors = backend.enumerate().unwrap();
+        assert_eq!(monitors.len(), 1);
+        assert_eq!(monitors[0].handle, Handle(1));
+        assert_eq!(backend.get_vcp(Handle(1), 0x10).unwrap().current, 20);
+        assert!(matches!(
+            backend.get_vcp(Handle(0), 0x10),
+            Err(MonitorError::NotFound(_))
+        ));
+
+        // Plugging it back in gives it its old handle back.
+        first.lock().unwrap().edid = edid;
+        let monitors = backend.enumerate().unwrap();
+        assert_eq!(monitors[0].handle, Handle(0));
+        assert_eq!(backend.get_vcp(Handle(0), 0x10).unwrap().current, 10);
+    }
+}
@@ -0,0 +1,456 @@
+mod caps;
+mod color;
+mod ddc;
+mod edid;
+mod error;
+mod input;
+#[cfg(target_os = "linux")]
+mod linux;
+mod sim;
+#[cfg(windows)]
+mod win32;
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+pub use caps::{feature_name, Capabilities};
+pub use color::{
+    parse_kelvin, ColorPreset, Temperature, VCP_BLACK_LEVELS, VCP_COLOR_PRESET, VCP_GAINS,
+};
+pub use edid::{Edid, Extension};
+pub use error::MonitorError;
+pub use input::InputSource;
+#[cfg(test)]
+pub use sim::write_fixture;
+pub use sim::SimBackend;
+
+pub const VCP_BRIGHTNESS: u8 = 0x10;
+pub const VCP_CONTRAST: u8 = 0x12;
+const VCP_INPUT_SOURCE: u8 = 0x60;
+pub const VCP_VOLUME: u8 = 0x62;
+
+const VCP_AUDIO_MUTE: u8 = 0x8d;
+const VCP_AUDIO_MUTE_ON: u32 = 0x01;
+const VCP_AUDIO_MUTE_OFF: u32 = 0x02;
+
+const VCP_POWER_MODE: u8 = 0xd6;
+const VCP_POWER_MODE_ON: u32 = 0x01;
+const VCP_POWER_MODE_STANDBY: u32 = 0x02;
+const VCP_POWER_MODE_SUSPEND: u32 = 0x03;
+const VCP_POWER_MODE_OFF: u32 = 0x04;
+const VCP_POWER_MODE_HARD_OFF: u32 = 0x05;
+
+/// The DPM/DPMS power states a monitor can be in, as given by VCP 0xd6.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PowerMode {
+    On,
+    Standby,
+    Suspend,
+    /// Soft off, the monitor wakes up again when it receives a signal.
+    Off,
+    /// Equivalent to pressing the power button.
+    HardOff,
+    #[serde(skip)]
+    Unknown(u32),
+}
+
+impl PowerMode {
+    fn from_vcp_code(value: u32) -> PowerMode {
+        match value {
+            VCP_POWER_MODE_ON => PowerMode::On,
+            VCP_POWER_MODE_STANDBY => PowerMode::Standby,
+            VCP_POWER_MODE_SUSPEND => PowerMode::Suspend,
+            VCP_POWER_MODE_OFF => PowerMode::Off,
+            VCP_POWER_MODE_HARD_OFF => PowerMode::HardOff,
+            value => PowerMode::Unknown(value),
+        }
+    }
+
+    fn vcp_code(&self) -> u32 {
+        match *self {
+            PowerMode::On => VCP_POWER_MODE_ON,
+            PowerMode::Standby => VCP_POWER_MODE_STANDBY,
+            PowerMode::Suspend => VCP_POWER_MODE_SUSPEND,
+            PowerMode::Off => VCP_POWER_MODE_OFF,
+            PowerMode::HardOff => VCP_POWER_MODE_HARD_OFF,
+            PowerMode::Unknown(value) => value,
+        }
+    }
+}
+
+impl fmt::Display for PowerMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowerMode::On => write!(f, "on"),
+            PowerMode::Standby => write!(f, "standby"),
+            PowerMode::Suspend => write!(f, "suspend"),
+            PowerMode::Off => write!(f, "off"),
+            PowerMode::HardOff => write!(f, "hard-off"),
+            PowerMode::Unknown(value) => write!(f, "unknown (0x{:02x})", value),
+        }
+    }
+}
+
+/// Opaque reference to a physical monitor. Only meaningful to the backend
+/// that handed it out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Handle(pub usize);
+
+/// A physical monitor as reported by `DisplayBackend::enumerate`.
+#[derive(Clone, Debug)]
+pub struct MonitorInfo {
+    pub name: String,
+    pub handle: Handle,
+    pub edid: Option<Edid>,
+    /// Where the monitor is connected, e.g. its I2C bus. Used to tell
+    /// monitors apart when their EDID doesn't.
+    pub path: String,
+}
+
+/// Whether a VCP feature holds a value, or triggers an action when set.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VcpType {
+    SetParameter,
+    Momentary,
+}
+
+/// The value of a VCP feature as reported by the monitor.
+#[derive(Copy, Clone, Debug)]
+pub struct VcpValue {
+    pub current: u32,
+    pub max: u32,
+    pub kind: VcpType,
+}
+
+/// Platform specific access to the connected monitors.
+pub trait DisplayBackend: Send + Sync {
+    fn enumerate(&self) -> Result<Vec<MonitorInfo>, MonitorError>;
+
+    fn get_vcp(&self, handle: Handle, code: u8) -> Result<VcpValue, MonitorError>;
+
+    fn set_vcp(&self, handle: Handle, code: u8, value: u32) -> Result<(), MonitorError>;
+
+    /// Returns the monitor's raw MCCS capabilities string.
+    fn capabilities(&self, handle: Handle) -> Result<String, MonitorError>;
+
+    fn power_mode(&self, handle: Handle) -> Result<PowerMode, MonitorError> {
+        self.get_vcp(handle, VCP_POWER_MODE)
+            .map(|v| PowerMode::from_vcp_code(v.current))
+    }
+
+    fn set_power_mode(&self, handle: Handle, mode: PowerMode) -> Result<(), MonitorError> {
+        self.set_vcp(handle, VCP_POWER_MODE, mode.vcp_code())
+    }
+}
+
+/// Returns the backend for the platform mona was built for.
+#[cfg(windows)]
+pub fn default_backend() -> Arc<dyn DisplayBackend> {
+    Arc::new(win32::Win32Backend::new())
+}
+
+/// Returns the backend for the platform mona was built for.
+#[cfg(target_os = "linux")]
+pub fn default_backend() -> Arc<dyn DisplayBackend> {
+    Arc::new(linux::LinuxBackend::new(linux::DevI2c))
+}
+
+/// Returns the backend for the platform mona was built for.
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn default_backend() -> Arc<dyn DisplayBackend> {
+    Arc::new(UnsupportedBackend)
+}
+
+/// Used on platforms without monitor control support. Reports no monitors.
+#[cfg(not(any(windows, target_os = "linux")))]
+struct UnsupportedBackend;
+
+#[cfg(not(any(windows, target_os = "linux")))]
+impl DisplayBackend for UnsupportedBackend {
+    fn enumerate(&self) -> Result<Vec<MonitorInfo>, MonitorError> {
+        Ok(vec![])
+    }
+
+    fn get_vcp(&self, _handle: Handle, _code: u8) -> Result<VcpValue, MonitorError> {
+        Err(MonitorError::DdcUnsupported)
+    }
+
+    fn set_vcp(&self, _handle: Handle, _code: u8, _value: u32) -> Result<(), MonitorError> {
+        Err(MonitorError::DdcUnsupported)
+    }
+
+    fn capabilities(&self, _handle: Handle) -> Result<String, MonitorError> {
+        Err(MonitorError::DdcUnsupported)
+    }
+}
+
+#[derive(Clone)]
+pub struct Monitor {
+    id: String,
+    index: i32,
+    name: String,
+    handle: Handle,
+    edid: Option<Edid>,
+    backend: Arc<dyn DisplayBackend>,
+}
+
+impl Monitor {
+    /// An id that stays the same for as long as the monitor is plugged into
+    /// the same port, and across ports if it reports a serial number, e.g.
+    /// `DEL-A0B8-ABC123`.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The monitor's position in enumeration order, starting at 1. This is
+    /// shorter to type than the id, but can change when monitors are plugged
+    /// in or out.
+    pub fn index(&self) -> i32 {
+        self.index
+    }
+
+    /// Whether `id` refers to this monitor, either by its stable id or by its
+    /// index.
+    pub fn has_id(&self, id: &str) -> bool {
+        self.id.eq_ignore_ascii_case(id) || id.parse() == Ok(self.index)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The backend's handle for the monitor, which can change when the
+    /// monitors are enumerated again.
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    /// The monitor's decoded EDID, if the backend was able to read it.
+    pub fn edid(&self) -> Option<&Edid> {
+        self.edid.as_ref()
+    }
+
+    pub fn power_mode(&self) -> Result<PowerMode, MonitorError> {
+        self.backend.power_mode(self.handle)
+    }
+
+    pub fn set_power_mode(&self, mode: PowerMode) -> Result<(), MonitorError> {
+        self.backend.set_power_mode(self.handle, mode)
+    }
+
+    pub fn input_source(&self) -> Result<InputSource, MonitorError> {
+        // Some monitors report garbage in the high byte, so only the low byte
+        // is significant.
+        self.get_vcp(VCP_INPUT_SOURCE)
+            .map(|v| InputSource(v.current as u8))
+    }
+
+    pub fn set_input_source(&self, source: InputSource) -> Result<(), MonitorError> {
+        self.set_vcp(VCP_INPUT_SOURCE, u32::from(source.0))
+    }
+
+    pub fn muted(&self) -> Result<bool, MonitorError> {
+        match self.get_vcp(VCP_AUDIO_MUTE)?.current {
+            VCP_AUDIO_MUTE_ON => Ok(true),
+            VCP_AUDIO_MUTE_OFF => Ok(false),
+            value => Err(MonitorError::Protocol(format!(
+                "invalid audio mute value: {}",
+                value
+            ))),
+        }
+    }
+
+    pub fn set_muted(&self, muted: bool) -> Result<(), MonitorError> {
+        let value = if muted {
+            VCP_AUDIO_MUTE_ON
+        } else {
+            VCP_AUDIO_MUTE_OFF
+        };
+        self.set_vcp(VCP_AUDIO_MUTE, value)
+    }
+
+    pub fn color_preset(&self) -> Result<ColorPreset, MonitorError> {
+        self.get_vcp(VCP_COLOR_PRESET)
+            .map(|v| ColorPreset(v.current as u8))
+    }
+
+    pub fn set_color_preset(&self, preset: ColorPreset) -> Result<(), MonitorError> {
+        self.set_vcp(VCP_COLOR_PRESET, u32::from(preset.0))
+    }
+
+    /// Sets the color temperature, using the monitor's preset for it if it
+    /// has one, and approximating it with the RGB gains otherwise.
+    pub fn set_color_temperature(&self, kelvin: u32) -> Result<Temperature, MonitorError> {
+        let presets = match self.capabilities() {
+            Ok(caps) => caps
+                .vcp
+                .into_iter()
+                .find(|feature| feature.code == VCP_COLOR_PRESET)
+                .map(|feature| feature.values)
+                .unwrap_or_default(),
+            Err(e) => {
+                log::debug!("failed to get capabilities of monitor {}: {}", self.id, e);
+                vec![]
+            }
+        };
+
+        if let Some(preset) = ColorPreset::for_kelvin(kelvin) {
+            if presets.contains(&preset.0) {
+                self.set_color_preset(preset)?;
+                return Ok(Temperature::Preset(preset));
+            }
+        }
+
+        // Most monitors ignore the gains outside of the user presets.
+        if presets.contains(&ColorPreset::USER1.0) {
+            let user = ColorPreset::USER1.0..=ColorPreset::USER1.0 + 2;
+            if !self.color_preset().is_ok_and(|p| user.contains(&p.0)) {
+                self.set_color_preset(ColorPreset::USER1)?;
+            }
+        }
+
+        let current = VCP_GAINS
+            .iter()
+            .map(|&code| self.get_vcp(code))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Scale from the brightest channel as it is, rather than from the
+        // maximum, so that the picture doesn't get brighter as it warms up.
+        let reference = match current.iter().map(|v| v.current).max() {
+            Some(0) | None => current.iter().map(|v| v.max).min().unwrap_or(0),
+            Some(brightest) => brightest,
+        };
+
+        let mut values = [0; 3];
+        let gains = color::temperature_gains(kelvin);
+        for (i, &code) in VCP_GAINS.iter().enumerate() {
+            values[i] = ((f64::from(reference) * gains[i]).round() as u32).min(current[i].max);
+            self.set_vcp(code, values[i])?;
+        }
+
+        Ok(Temperature::Gains(values))
+    }
+
+    pub fn capabilities(&self) -> Result<Capabilities, MonitorError> {
+        Capabilities::parse(&self.backend.capabilities(self.handle)?)
+    }
+
+    pub fn get_vcp(&self, code: u8) -> Result<VcpValue, MonitorError> {
+        self.backend.get_vcp(self.handle, code)
+    }
+
+    pub fn set_vcp(&self, code: u8, value: u32) -> Result<(), MonitorError> {
+        self.backend.set_vcp(self.handle, code, value)
+    }
+}
+
+pub fn get_monitors(backend: &Arc<dyn DisplayBackend>) -> Result<Vec<Monitor>, MonitorError> {
+    let infos = backend.enumerate()?;
+    let ids: Vec<String> = infos.iter().map(stable_id).collect();
+
+    let monitors = (1..)
+        .zip(infos)
+        .zip(&ids)
+        .map(|((index, info), id)| {
+            // Some monitors report the same made up serial number for every
+            // unit, in which case only the port can tell them apart. All of
+            // them get the port, so that none of their ids depend on which
+            // was found first.
+            let id = if ids.iter().filter(|other| *other == id).count() > 1 {
+                format!("{}@{}", id, info.path)
+            } else {
+                id.clone()
+            };
+
+            Monitor {
+                id,
+                index,
+                name: info.name,
+                handle: info.handle,
+                edid: info.edid,
+                backend: backend.clone(),
+            }
+        })
+        .collect();
+
+    Ok(monitors)
+}
+
+/// Derives an id from the manufacturer, product and serial number in the
+/// monitor's EDID, falling back to where it is connected if it doesn't have
+/// a serial number.
+fn stable_id(info: &MonitorInfo) -> String {
+    let edid = match &info.edid {
+        Some(edid) => edid,
+        None => return info.path.clone(),
+    };
+
+    let product = format!("{}-{:04X}", edid.manufacturer, edid.product);
+    match edid.serial() {
+        Some(serial) => {
+            let serial: String = serial
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            format!("{}-{}", product, serial)
+        }
+        None => format!("{}@{}", product, info.path),
+    }
+}
+
+/// Parses a VCP code given either in decimal or as `0x`-prefixed hex.
+pub fn parse_vcp_code(code: &str) -> Result<u8, Box<dyn Error>> {
+    let res = match code.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => code.parse(),
+    };
+    res.map_err(|_| format!("invalid vcp code: {}", code).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suffixes_every_colliding_id() {
+        let path = write_fixture(
+            "ids",
+            r#"
+            [[monitors]]
+            id = 1
+            name = "Dell"
+            edid = { manufacturer = "DEL", product = 0xa0b8, serial = "0000" }
+
+            [[monitors]]
+            id = 2
+            name = "LG"
+            edid = { manufacturer = "GSM", product = 0x5b09, serial = "1234" }
+
+            [[monitors]]
+            id = 3
+            name = "Dell"
+            edid = { manufacturer = "DEL", product = 0xa0b8, serial = "0000" }
+            "#,
+        );
+        let backend: Arc<dyn DisplayBackend> = Arc::new(SimBackend::from_file(path).unwrap());
+
+        let ids: Vec<_> = get_monitors(&backend)
+            .unwrap()
+            .iter()
+            .map(|monitor| monitor.id().to_owned())
+            .collect();
+
+        assert_eq!(
+            ids,
+            [
+                "DEL-A0B8-0000@sim:1",
+                "GSM-5B09-1234",
+                "DEL-A0B8-0000@sim:3"
+            ]
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::Deserialize;
+
+use super::{
+    parse_vcp_code, DisplayBackend, Edid, Handle, MonitorError, MonitorInfo, PowerMode, VcpType,
+    VcpValue, VCP_POWER_MODE, VCP_POWER_MODE_HARD_OFF,
+};
+
+/// Serves a set of fake monitors described by a TOML or JSON fixture, e.g.
+///
+/// ```toml
+/// [[monitors]]
+/// id = 1
+/// name = "DELL U2720Q"
+/// power = "on"
+/// vcp = { "0x10" = 50, "0x12" = { current = 75, max = 80 } }
+/// ```
+///
+/// VCP values given as a plain number have a maximum of 100. A
+/// `capabilities` string can be given, otherwise one is made up from the
+/// monitor's VCP features. An `edid` can be given either as a hex string of
+/// the raw blocks, or as a table of its identifying fields:
+///
+/// ```toml
+/// edid = { manufacturer = "DEL", product = 0xa0b8, serial = "ABC123", year = 2020 }
+/// ```
+///
+/// The fixture is re-read on every enumeration, so monitors can be plugged
+/// in and out by editing it. Monitors that are still present keep whatever
+/// state they have been given since they were first seen.
+pub struct SimBackend {
+    path: PathBuf,
+    monitors: Mutex<Vec<SimMonitor>>,
+}
+
+struct SimMonitor {
+    id: usize,
+    name: String,
+    vcp: HashMap<u8, VcpValue>,
+    capabilities: Option<String>,
+    edid: Option<Edid>,
+}
+
+#[derive(Deserialize)]
+struct Fixture {
+    #[serde(default)]
+    monitors: Vec<FixtureMonitor>,
+}
+
+#[derive(Deserialize)]
+struct FixtureMonitor {
+    id: usize,
+    name: String,
+    #[serde(default = "default_power_mode")]
+    power: PowerMode,
+    #[serde(default)]
+    vcp: HashMap<String, FixtureVcpValue>,
+    capabilities: Option<String>,
+    edid: Option<FixtureEdid>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FixtureVcpValue {
+    Current(u32),
+    Full { current: u32, max: u32 },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FixtureEdid {
+    Raw(String),
+    Fields {
+        manufacturer: String,
+        #[serde(default)]
+        product: u16,
+        serial: Option<String>,
+        #[serde(default = "default_year")]
+        year: u16,
+    },
+}
+
+fn default_year() -> u16 {
+    2020
+}
+
+fn default_power_mode() -> PowerMode {
+    PowerMode::On
+}
+
+impl SimBackend {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<SimBackend, Box<dyn Error>> {
+        let path = path.as_ref().to_owned();
+        let monitors = load_fixture(&path)?;
+
+        Ok(SimBackend {
+            path,
+            monitors: Mutex::new(monitors),
+        })
+    }
+
+    fn with_monitor<T>(
+        &self,
+        handle: Handle,
+        f: impl FnOnce(&mut SimMonitor) -> Result<T, MonitorError>,
+    ) -> Result<T, MonitorError> {
+        let mut monitors = self.monitors.lock().unwrap();
+        let monitor = monitors
+            .iter_mut()
+            .find(|m| m.id == handle.0)
+            .ok_or_else(|| {
+                MonitorError::NotFound(format!("simulated monitor with id {}", handle.0))
+            })?;
+        f(monitor)
+    }
+}
+
+impl DisplayBackend for SimBackend {
+    fn enumerate(&self) -> Result<Vec<MonitorInfo>, MonitorError> {
+        let mut monitors = self.monitors.lock().unwrap();
+
+        match load_fixture(&self.path) {
+            Ok(fixture) => {
+                let mut previous = mem::take(&mut *monitors);
+                *monitors = fixture
+                    .into_iter()
+                    .map(|m| match previous.iter().position(|p| p.id == m.id) {
+                        Some(i) => previous.swap_remove(i),
+                        None => m,
+                    })
+                    .collect();
+            }
+            Err(e) => log::warn!("failed to reload {}: {}", self.path.display(), e),
+        }
+
+        let monitors = monitors
+            .iter()
+            .map(|m| MonitorInfo {
+                name: m.name.clone(),
+                handle: Handle(m.id),
+                edid: m.edid.clone(),
+                path: format!("sim:{}", m.id),
+            })
+            .collect();
+
+        Ok(monitors)
+    }
+
+    fn get_vcp(&self, handle: Handle, code: u8) -> Result<VcpValue, MonitorError> {
+        self.with_monitor(handle, |monitor| {
+            monitor
+                .vcp
+                .get(&code)
+                .copied()
+                .ok_or(MonitorError::UnsupportedFeature(code))
+        })
+    }
+
+    fn set_vcp(&self, handle: Handle, code: u8, value: u32) -> Result<(), MonitorError> {
+        self.with_monitor(handle, |monitor| match monitor.vcp.get_mut(&code) {
+            Some(current) if value <= current.max => {
+                current.current = value;
+                Ok(())
+            }
+            Some(current) => Err(MonitorError::InvalidValue(format!(
+                "{} is out of range for vcp feature 0x{:02x} (max {})",
+                value, code, current.max
+            ))),
+            None => Err(MonitorError::UnsupportedFeature(code)),
+        })
+    }
+
+    fn capabilities(&self, handle: Handle) -> Result<String, MonitorError> {
+        self.with_monitor(handle, |monitor| {
+            if let Some(caps) = &monitor.capabilities {
+                return Ok(caps.clone());
+            }
+
+            let mut codes: Vec<_> = monitor.vcp.keys().collect();
+            codes.sort();
+
+            let codes: Vec<_> = codes.iter().map(|code| format!("{:02X}", code)).collect();
+
+            Ok(format!(
+                "(prot(monitor)type(LCD)model({})cmds(01 02 03 F3)vcp({})mccs_ver(2.1))",
+                monitor.name,
+                codes.join(" ")
+            ))
+        })
+    }
+}
+
+/// Writes a fixture to a file of its own in the temp directory, for tests
+/// that need a `SimBackend`.
+#[cfg(test)]
+pub fn write_fixture(name: &str, contents: &str) -> PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Tests run in parallel, so each fixture gets a file to itself.
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "mona-{}-{}-{}.toml",
+        std::process::id(),
+        name,
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn load_fixture(path: &Path) -> Result<Vec<SimMonitor>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    let fixture: Fixture = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&contents)?,
+        _ => toml::from_str(&contents)?,
+    };
+
+    fixture
+        .monitors
+        .into_iter()
+        .map(|m| {
+            let mut vcp = HashMap::new();
+            for (code, value) in m.vcp {
+                let (current, max) = match value {
+                    FixtureVcpValue::Current(current) => (current, 100),
+                    FixtureVcpValue::Full { current, max } => (current, max),
+                };
+                vcp.insert(
+                    parse_vcp_code(&code)?,
+                    VcpValue {
+                        current,
+                        max,
+                        kind: VcpType::SetParameter,
+                    },
+                );
+            }
+
+            vcp.insert(
+                VCP_POWER_MODE,
+                VcpValue {
+                    current: m.power.vcp_code(),
+                    max: VCP_POWER_MODE_HARD_OFF,
+                    kind: VcpType::SetParameter,
+                },
+            );
+
+            let edid = match m.edid {
+                Some(edid) => Some(fixture_edid(edid, &m.name)?),
+                None => None,
+            };
+
+            Ok(SimMonitor {
+                id: m.id,
+                name: m.name,
+                vcp,
+                capabilities: m.capabilities,
+                edid,
+            })
+        })
+        .collect()
+}
+
+fn fixture_edid(edid: FixtureEdid, name: &str) -> Result<Edid, Box<dyn Error>> {
+    match edid {
+        FixtureEdid::Raw(hex) => {
+            let hex: String = hex.split_whitespace().collect();
+            let bytes = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or(""), 16))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("invalid edid hex for {}", name))?;
+            Ok(Edid::parse(&bytes)?)
+        }
+        FixtureEdid::Fields {
+            manufacturer,
+            product,
+            serial,
+            year,
+        } => Ok(Edid {
+            manufacturer,
+            product,
+            serial_number: 0,
+            serial_string: serial,
+            name: Some(name.to_owned()),
+            text: None,
+            week: None,
+            year,
+            model_year: false,
+            version: "1.4".to_owned(),
+            digital: true,
+            size_cm: None,
+            preferred_mode: None,
+            extensions: vec![],
+        }),
+    }
+}
@@ -0,0 +1,560 @@
+use std::ffi::OsStr;
+use std::iter;
+use std::mem;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr;
+use std::sync::Mutex;
+
+use winapi::{
+    shared::{
+        basetsd::UINT32,
+        windef::{HDC, HMONITOR, RECT},
+        winerror::{
+            ERROR_GRAPHICS_DDCCI_INVALID_DATA, ERROR_GRAPHICS_DDCCI_INVALID_MESSAGE_CHECKSUM,
+            ERROR_GRAPHICS_DDCCI_INVALID_MESSAGE_COMMAND,
+            ERROR_GRAPHICS_DDCCI_INVALID_MESSAGE_LENGTH, ERROR_GRAPHICS_DDCCI_VCP_NOT_SUPPORTED,
+            ERROR_GRAPHICS_I2C_DEVICE_DOES_NOT_EXIST, ERROR_GRAPHICS_I2C_NOT_SUPPORTED,
+            ERROR_GRAPHICS_INVALID_PHYSICAL_MONITOR_HANDLE,
+            ERROR_GRAPHICS_MONITOR_NO_LONGER_EXISTS, ERROR_SUCCESS, HRESULT,
+        },
+    },
+    um::{
+        errhandlingapi::GetLastError,
+        lowlevelmonitorconfigurationapi::{
+            CapabilitiesRequestAndCapabilitiesReply, GetCapabilitiesStringLength,
+            GetVCPFeatureAndVCPFeatureReply, SetVCPFeature, MC_MOMENTARY, MC_VCP_CODE_TYPE,
+        },
+        physicalmonitorenumerationapi::{
+            DestroyPhysicalMonitor, GetNumberOfPhysicalMonitorsFromHMONITOR,
+            GetPhysicalMonitorsFromHMONITOR, PHYSICAL_MONITOR,
+        },
+        wingdi::{
+            DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME, DISPLAYCONFIG_DEVICE_INFO_HEADER,
+            DISPLAYCONFIG_MODE_INFO, DISPLAYCONFIG_MODE_INFO_TYPE_TARGET, DISPLAYCONFIG_PATH_INFO,
+            DISPLAYCONFIG_TARGET_DEVICE_NAME, DISPLAYCONFIG_TOPOLOGY_ID, DISPLAY_DEVICEW,
+            QDC_ALL_PATHS,
+        },
+        winnt::{HANDLE, LONG},
+        winreg::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_BINARY},
+        winuser::{EnumDisplayDevicesW, EnumDisplayMonitors, GetMonitorInfoW, MONITORINFOEXW},
+    },
+};
+
+use super::{DisplayBackend, Edid, Handle, MonitorError, MonitorInfo, VcpType, VcpValue};
+
+/// Controls monitors through the Win32 monitor configuration API.
+pub struct Win32Backend {
+    /// Physical monitors by handle. A monitor keeps its handle for good, so
+    /// that handles given out before an enumeration still point at the same
+    /// monitor after.
+    monitors: Mutex<Vec<KnownMonitor>>,
+}
+
+struct KnownMonitor {
+    path: String,
+    /// The physical monitor handle, or `None` while the monitor is gone.
+    /// Kept as a `usize` since `HANDLE` can't be sent between threads.
+    physical: Option<usize>,
+}
+
+impl Win32Backend {
+    pub fn new() -> Win32Backend {
+        Win32Backend {
+            monitors: Mutex::new(vec![]),
+        }
+    }
+
+    fn with_monitor<T>(
+        &self,
+        handle: Handle,
+        f: impl FnOnce(HANDLE) -> Result<T, MonitorError>,
+    ) -> Result<T, MonitorError> {
+        let monitors = self.monitors.lock().unwrap();
+        let physical = monitors
+            .get(handle.0)
+            .and_then(|known| known.physical)
+            .ok_or_else(|| {
+                MonitorError::NotFound(format!("physical monitor for handle {}", handle.0))
+            })?;
+
+        f(physical as HANDLE)
+    }
+}
+
+impl Default for Win32Backend {
+    fn default() -> Win32Backend {
+        Win32Backend::new()
+    }
+}
+
+impl Drop for Win32Backend {
+    fn drop(&mut self) {
+        for known in self.monitors.get_mut().unwrap().iter_mut() {
+            if let Some(physical) = known.physical.take() {
+                destroy_physical_monitor(physical as HANDLE);
+            }
+        }
+    }
+}
+
+impl DisplayBackend for Win32Backend {
+    fn enumerate(&self) -> Result<Vec<MonitorInfo>, MonitorError> {
+        let display_devices = get_display_devices()?;
+        let mut display_monitors = get_display_monitors();
+
+        let mut known = self.monitors.lock().unwrap();
+        let mut monitors = Vec::new();
+
+        // Windows gives out new physical monitor handles every time, so the
+        // ones from the last enumeration are released.
+        for entry in known.iter_mut() {
+            if let Some(physical) = entry.physical.take() {
+                destroy_physical_monitor(physical as HANDLE);
+            }
+        }
+
+        for device in display_devices {
+            let monitor = match display_monitors
+                .iter()
+                .position(|monitor| device.device_name.starts_with(&monitor.device_name))
+            {
+                Some(i) => display_monitors.remove(i),
+                None => {
+                    log::warn!(
+                        "skipping display device {}: no physical monitor found",
+                        device.device_name
+                    );
+                    continue;
+                }
+            };
+
+            let handle = match known.iter().position(|entry| entry.path == device.path) {
+                Some(handle) => handle,
+                None => {
+                    known.push(KnownMonitor {
+                        path: device.path.clone(),
+                        physical: None,
+                    });
+                    known.len() - 1
+                }
+            };
+            known[handle].physical = Some(monitor.handle as usize);
+
+            monitors.push(MonitorInfo {
+                name: device.friendly_name,
+                handle: Handle(handle),
+                edid: device.edid,
+                path: device.path,
+            });
+        }
+
+        for monitor in display_monitors {
+            destroy_physical_monitor(monitor.handle);
+        }
+
+        Ok(monitors)
+    }
+
+    fn get_vcp(&self, handle: Handle, code: u8) -> Result<VcpValue, MonitorError> {
+        self.with_monitor(handle, |physical| {
+            let mut kind: MC_VCP_CODE_TYPE = 0;
+            let mut current = 0;
+            let mut max = 0;
+            let res = unsafe {
+                GetVCPFeatureAndVCPFeatureReply(physical, code, &mut kind, &mut current, &mut max)
+            };
+            if res == 1 {
+                Ok(VcpValue {
+                    current,
+                    max,
+                    kind: if kind == MC_MOMENTARY {
+                        VcpType::Momentary
+                    } else {
+                        VcpType::SetParameter
+                    },
+                })
+            } else {
+                Err(last_error(
+                    Some(code),
+                    format!("failed to get vcp feature 0x{:02x}", code),
+                ))
+            }
+        })
+    }
+
+    fn set_vcp(&self, handle: Handle, code: u8, value: u32) -> Result<(), MonitorError> {
+        self.with_monitor(handle, |physical| {
+            if unsafe { SetVCPFeature(physical, code, value) } == 1 {
+                Ok(())
+            } else {
+                Err(last_error(
+                    Some(code),
+                    format!("failed to set vcp feature 0x{:02x}", code),
+                ))
+            }
+        })
+    }
+
+    fn capabilities(&self, handle: Handle) -> Result<String, MonitorError> {
+        self.with_monitor(handle, |physical| {
+            let mut len = 0;
+            if unsafe { GetCapabilitiesStringLength(physical, &mut len) } != 1 {
+                return Err(last_error(
+                    None,
+                    "failed to get capabilities string length".to_owned(),
+                ));
+            }
+
+            let mut buf = vec![0u8; len as usize];
+            let res = unsafe {
+                CapabilitiesRequestAndCapabilitiesReply(physical, buf.as_mut_ptr().cast(), len)
+            };
+            if res != 1 {
+                return Err(last_error(
+                    None,
+                    "failed to get capabilities string".to_owned(),
+                ));
+            }
+
+            let len = buf.iter().take_while(|&&c| c != 0).count();
+            Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+        })
+    }
+}
+
+#[derive(Debug)]
+struct DisplayDevice {
+    friendly_name: String,
+    device_name: String,
+    edid: Option<Edid>,
+    path: String,
+}
+
+fn get_display_devices() -> Result<Vec<DisplayDevice>, MonitorError> {
+    let device_map = get_device_map();
+
+    let mut num_paths = 0;
+    let mut num_modes = 0;
+
+    let res = unsafe { GetDisplayConfigBufferSizes(QDC_ALL_PATHS, &mut num_paths, &mut num_modes) };
+    if res != ERROR_SUCCESS as LONG {
+        return Err(MonitorError::Os {
+            code: res,
+            message: "failed to get display config buffer sizes".to_owned(),
+        });
+    }
+
+    let mut paths = Vec::with_capacity(num_paths as usize);
+    let mut modes = Vec::with_capacity(num_modes as usize);
+
+    unsafe {
+        let res = QueryDisplayConfig(
+            QDC_ALL_PATHS,
+            &mut num_paths,
+            paths.as_mut_ptr(),
+            &mut num_modes,
+            modes.as_mut_ptr(),
+            ptr::null_mut(),
+        );
+
+        if res != ERROR_SUCCESS as LONG {
+            return Err(MonitorError::Os {
+                code: res,
+                message: "failed to query display config".to_owned(),
+            });
+        }
+
+        paths.set_len(num_paths as usize);
+        modes.set_len(num_modes as usize);
+    }
+
+    let mut devices = vec![];
+
+    for mode in modes {
+        if mode.infoType == DISPLAYCONFIG_MODE_INFO_TYPE_TARGET {
+            let mut name: DISPLAYCONFIG_TARGET_DEVICE_NAME = unsafe { mem::zeroed() };
+
+            name.header = DISPLAYCONFIG_DEVICE_INFO_HEADER {
+                _type: DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+                size: mem::size_of::<DISPLAYCONFIG_TARGET_DEVICE_NAME>() as u32,
+                adapterId: mode.adapterId,
+                id: mode.id,
+            };
+
+            unsafe {
+                DisplayConfigGetDeviceInfo(
+                    (&mut name as *mut DISPLAYCONFIG_TARGET_DEVICE_NAME).cast(),
+                );
+            }
+
+            let friendly_name = utf16_nt_to_string(&name.monitorFriendlyDeviceName);
+            let device_path = name.monitorDevicePath;
+            let device_name = match device_map.iter().find(|(id, _)| id[..] == device_path[..]) {
+                Some((_, device_name)) => device_name,
+                None => {
+                    log::warn!(
+                        "skipping {}: no display device found",
+                        utf16_nt_to_string(&device_path)
+                    );
+                    continue;
+                }
+            };
+
+            // Strip the `\\?\` prefix and interface GUID from the device path,
+            // leaving e.g. `DISPLAY#DEL40F3#5&1a2b3c4d&0&UID4352`.
+            let path = utf16_nt_to_string(&device_path);
+            let path = path.trim_start_matches(r"\\?\");
+            let path = match path.rsplit_once('#') {
+                Some((instance, _)) => instance.to_owned(),
+                None => path.to_owned(),
+            };
+
+            let edid = read_edid(&path).and_then(|edid| {
+                Edid::parse(&edid)
+                    .map_err(|e| log::debug!("invalid edid for {}: {}", friendly_name, e))
+                    .ok()
+            });
+
+            devices.push(DisplayDevice {
+                friendly_name,
+                device_name: utf16_nt_to_string(device_name),
+                edid,
+                path,
+            });
+        }
+    }
+
+    Ok(devices)
+}
+
+/// Reads the EDID that Windows caches in the registry for the monitor with
+/// the given device instance path. An instance such as
+/// `DISPLAY#DEL40F3#5&1a2b3c4d&0&UID4352` has its EDID under
+/// `HKLM\SYSTEM\CurrentControlSet\Enum\DISPLAY\DEL40F3\5&1a2b3c4d&0&UID4352`.
+fn read_edid(path: &str) -> Option<Vec<u8>> {
+    let parts: Vec<_> = path.split('#').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+
+    let key = format!(
+        r"SYSTEM\CurrentControlSet\Enum\{}\{}\{}\Device Parameters",
+        parts[0], parts[1], parts[2]
+    );
+
+    let key = to_utf16_nt(&key);
+    let value = to_utf16_nt("EDID");
+
+    let mut len = 0;
+    let res = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_REG_BINARY,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut len,
+        )
+    };
+
+    if res != ERROR_SUCCESS as LONG {
+        return None;
+    }
+
+    let mut edid = vec![0u8; len as usize];
+    let res = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_REG_BINARY,
+            ptr::null_mut(),
+            edid.as_mut_ptr().cast(),
+            &mut len,
+        )
+    };
+
+    if res != ERROR_SUCCESS as LONG {
+        return None;
+    }
+
+    edid.truncate(len as usize);
+    Some(edid)
+}
+
+fn to_utf16_nt(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(iter::once(0)).collect()
+}
+
+// We just use a vec here since there are probably
+// very few items (< 5)
+type DeviceMap = Vec<([u16; 128], [u16; 32])>;
+
+fn get_device_map() -> DeviceMap {
+    let mut map = DeviceMap::new();
+
+    let mut device = unsafe {
+        let mut device: DISPLAY_DEVICEW = mem::zeroed();
+        device.cb = mem::size_of::<DISPLAY_DEVICEW>() as u32;
+        device
+    };
+
+    let mut i = 0;
+    unsafe {
+        while EnumDisplayDevicesW(ptr::null(), i, &mut device, 0) != 0 {
+            let name = device.DeviceName.to_owned();
+            if EnumDisplayDevicesW(name.as_ptr(), 0, &mut device, 1) != 0 {
+                map.push((device.DeviceID.to_owned(), device.DeviceName.to_owned()));
+            }
+
+            i += 1
+        }
+    }
+
+    map
+}
+
+#[derive(Debug)]
+struct DisplayMonitor {
+    device_name: String,
+    handle: HANDLE,
+}
+
+fn get_display_monitors() -> Vec<DisplayMonitor> {
+    let mut monitors = Vec::<DisplayMonitor>::new();
+
+    unsafe {
+        EnumDisplayMonitors(
+            ptr::null_mut(),
+            ptr::null_mut(),
+            Some(enum_monitor_proc),
+            mem::transmute(&mut monitors),
+        );
+    }
+
+    monitors
+}
+
+unsafe extern "system" fn enum_monitor_proc(
+    hmonitor: HMONITOR,
+    _hdc: HDC,
+    _rect: *mut RECT,
+    data: isize,
+) -> i32 {
+    let monitors = data as *mut Vec<DisplayMonitor>;
+
+    let info = match get_monitor_info(hmonitor) {
+        Some(info) => info,
+        None => {
+            eprint!("failed to get monitor info for hmonitor {:?}", hmonitor);
+            return 1;
+        }
+    };
+
+    let physical_monitors = match get_physical_monitors(hmonitor) {
+        Some(v) => v,
+        None => {
+            eprintln!("no physical monitors found for hmonitor {:?}", hmonitor);
+            return 1;
+        }
+    };
+
+    for monitor in physical_monitors {
+        (*monitors).push(DisplayMonitor {
+            device_name: utf16_nt_to_string(&info.szDevice),
+            handle: monitor.hPhysicalMonitor,
+        })
+    }
+
+    1
+}
+
+fn get_monitor_info(hmonitor: HMONITOR) -> Option<MONITORINFOEXW> {
+    let mut info: MONITORINFOEXW = unsafe { mem::zeroed() };
+    info.cbSize = mem::size_of::<MONITORINFOEXW>() as u32;
+
+    unsafe {
+        if GetMonitorInfoW(hmonitor, (&mut info as *mut MONITORINFOEXW).cast()) == 0 {
+            return None;
+        }
+    }
+
+    Some(info)
+}
+
+fn get_physical_monitors(hmonitor: HMONITOR) -> Option<Vec<PHYSICAL_MONITOR>> {
+    let mut count = 0;
+    unsafe {
+        if GetNumberOfPhysicalMonitorsFromHMONITOR(hmonitor, &mut count) == 0 {
+            return None;
+        }
+    }
+
+    let mut monitors = Vec::with_capacity(count as usize);
+    unsafe {
+        if GetPhysicalMonitorsFromHMONITOR(hmonitor, count, monitors.as_mut_ptr()) == 0 {
+            return None;
+        }
+        monitors.set_len(count as usize);
+    }
+
+    Some(monitors)
+}
+
+fn destroy_physical_monitor(handle: HANDLE) {
+    if unsafe { DestroyPhysicalMonitor(handle) } == 0 {
+        log::debug!("failed to destroy physical monitor {:?}", handle);
+    }
+}
+
+/// Converts the calling thread's last error into a `MonitorError`, using
+/// `message` to describe what failed if it doesn't map to anything more
+/// specific.
+fn last_error(vcp_code: Option<u8>, message: String) -> MonitorError {
+    let code = unsafe { GetLastError() } as HRESULT;
+    match code {
+        ERROR_GRAPHICS_DDCCI_VCP_NOT_SUPPORTED => match vcp_code {
+            Some(vcp_code) => MonitorError::UnsupportedFeature(vcp_code),
+            None => MonitorError::DdcUnsupported,
+        },
+        ERROR_GRAPHICS_I2C_NOT_SUPPORTED | ERROR_GRAPHICS_I2C_DEVICE_DOES_NOT_EXIST => {
+            MonitorError::DdcUnsupported
+        }
+        ERROR_GRAPHICS_DDCCI_INVALID_MESSAGE_CHECKSUM => MonitorError::Checksum,
+        ERROR_GRAPHICS_DDCCI_INVALID_DATA
+        | ERROR_GRAPHICS_DDCCI_INVALID_MESSAGE_COMMAND
+        | ERROR_GRAPHICS_DDCCI_INVALID_MESSAGE_LENGTH => MonitorError::Protocol(message),
+        ERROR_GRAPHICS_INVALID_PHYSICAL_MONITOR_HANDLE
+        | ERROR_GRAPHICS_MONITOR_NO_LONGER_EXISTS => {
+            MonitorError::NotFound("physical monitor".to_owned())
+        }
+        code => MonitorError::Os { code, message },
+    }
+}
+
+/// Converts a null terminated buffer of utf16 characters to a `String`.
+fn utf16_nt_to_string(buf: &[u16]) -> String {
+    let len = buf.iter().take_while(|&&i| i != 0).count();
+    String::from_utf16_lossy(&buf[0..len])
+}
+
+extern "system" {
+    pub fn GetDisplayConfigBufferSizes(
+        flags: UINT32,
+        numPathArrayElements: *mut UINT32,
+        numModeInfoArrayElements: *mut UINT32,
+    ) -> LONG;
+
+    pub fn QueryDisplayConfig(
+        flags: UINT32,
+        numPathArrayElements: *mut UINT32,
+        pathArray: *mut DISPLAYCONFIG_PATH_INFO,
+        numModeInfoArrayElements: *mut UINT32,
+        modeInfoArray: *mut DISPLAYCONFIG_MODE_INFO,
+        currentTopologyId: *mut DISPLAYCONFIG_TOPOLOGY_ID,
+    ) -> LONG;
+
+    pub fn DisplayConfigGetDeviceInfo(requestPacket: *mut DISPLAYCONFIG_DEVICE_INFO_HEADER)
+        -> LONG;
+}
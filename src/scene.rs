@@ -0,0 +1,335 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::adjustment::Adjustment;
+use crate::calibration::Calibration;
+use crate::monitors::{self, InputSource, Monitor, MonitorError, PowerMode};
+use crate::selector::{Aliases, Selector};
+
+/// A named set of changes to apply to several monitors at once, given in the
+/// config as a list of steps, e.g.
+///
+/// ```toml
+/// [[scenes.movie]]
+/// monitors = "left"
+/// power = "off"
+///
+/// [[scenes.movie]]
+/// monitors = "right"
+/// brightness = 30
+/// input = "hdmi1"
+/// ```
+pub type Scene = Vec<Step>;
+
+/// Changes to apply to the monitors matching a selector.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub monitors: Selector,
+    /// `off` is soft off, and `hard-off` is off as if at the power button.
+    pub power: Option<PowerMode>,
+    pub input: Option<InputSource>,
+    pub brightness: Option<Adjustment>,
+    pub contrast: Option<Adjustment>,
+    /// Raw VCP values keyed by code, e.g. `{ "0x14" = 5 }`.
+    #[serde(default)]
+    pub vcp: BTreeMap<String, u32>,
+}
+
+/// A single change made to a single monitor while applying a scene.
+#[derive(Copy, Clone, Debug)]
+pub enum Action {
+    Power(PowerMode),
+    Input(InputSource),
+    Level(&'static str, u8, Adjustment),
+    Vcp(u8, u32),
+}
+
+/// The outcome of applying an action to a monitor.
+pub struct StepResult<'a> {
+    pub monitor: &'a Monitor,
+    pub action: Action,
+    pub result: Result<String, MonitorError>,
+}
+
+impl Step {
+    /// The actions making up the step, in the order they are applied.
+    /// Monitors are turned on before anything else is changed, and only
+    /// turned off once everything else has been.
+    pub fn actions(&self) -> Result<Vec<Action>, Box<dyn Error>> {
+        let mut actions = vec![];
+
+        if self.power == Some(PowerMode::On) {
+            actions.push(Action::Power(PowerMode::On));
+        }
+
+        if let Some(input) = self.input {
+            actions.push(Action::Input(input));
+        }
+
+        if let Some(brightness) = self.brightness {
+            actions.push(Action::Level(
+                "brightness",
+                monitors::VCP_BRIGHTNESS,
+                brightness,
+            ));
+        }
+
+        if let Some(contrast) = self.contrast {
+            actions.push(Action::Level("contrast", monitors::VCP_CONTRAST, contrast));
+        }
+
+        for (code, value) in &self.vcp {
+            actions.push(Action::Vcp(monitors::parse_vcp_code(code)?, *value));
+        }
+
+        match self.power {
+            Some(PowerMode::On) | None => {}
+            Some(mode) => actions.push(Action::Power(mode)),
+        }
+
+        Ok(actions)
+    }
+}
+
+impl Action {
+    fn apply(&self, monitor: &Monitor, calibration: &Calibration) -> Result<String, MonitorError> {
+        match *self {
+            Action::Power(mode) => monitor.set_power_mode(mode).map(|_| "ok".to_owned()),
+            Action::Input(source) => monitor.set_input_source(source).map(|_| "ok".to_owned()),
+            Action::Level(_, code, adjustment) => {
+                let value = monitor.get_vcp(code)?;
+                let new = calibration.adjust(monitor, code, adjustment, &value);
+                monitor.set_vcp(code, new)?;
+                Ok(format!("{} -> {}", value.current, new))
+            }
+            Action::Vcp(code, value) => monitor.set_vcp(code, value).map(|_| "ok".to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Power(mode) => write!(f, "power {}", mode),
+            Action::Input(source) => write!(f, "input {}", source),
+            Action::Level(name, _, adjustment) => write!(f, "{} {}", name, adjustment),
+            Action::Vcp(code, value) => write!(f, "vcp 0x{:02x} {}", code, value),
+        }
+    }
+}
+
+/// Applies the scene to the monitors. Every step is resolved up front, so
+/// nothing is changed if any of them don't make sense. After that, a failed
+/// action doesn't stop the rest from being applied. Brightness is mapped
+/// through the monitors' calibration curves, the same as `mona brightness`.
+pub fn apply<'a>(
+    scene: &[Step],
+    monitors: &'a [Monitor],
+    aliases: &Aliases,
+    calibration: &Calibration,
+) -> Result<Vec<StepResult<'a>>, Box<dyn Error>> {
+    let mut batch = vec![];
+
+    for step in scene {
+        let selected = step.monitors.select(monitors, aliases)?;
+        let actions = step.actions()?;
+        for monitor in selected {
+            batch.extend(actions.iter().map(|action| (monitor, *action)));
+        }
+    }
+
+    let results = batch
+        .into_iter()
+        .map(|(monitor, action)| StepResult {
+            monitor,
+            action,
+            result: action.apply(monitor, calibration),
+        })
+        .collect();
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+
+    use crate::calibration::Curve;
+    use crate::monitors::{DisplayBackend, SimBackend};
+
+    #[derive(Deserialize)]
+    struct Scenes {
+        scenes: BTreeMap<String, Scene>,
+    }
+
+    fn scene(toml: &str) -> Scene {
+        let mut config: Scenes = toml::from_str(toml).unwrap();
+        config.scenes.remove("test").unwrap()
+    }
+
+    fn monitors() -> Vec<Monitor> {
+        // The LG has no input select feature.
+        let path = monitors::write_fixture(
+            "scene",
+            r#"
+            [[monitors]]
+            id = 1
+            name = "Dell"
+            power = "standby"
+            vcp = { "0x10" = 50, "0x12" = 50, "0x60" = 15 }
+
+            [[monitors]]
+            id = 2
+            name = "LG"
+            vcp = { "0x10" = 50, "0x12" = 50 }
+            "#,
+        );
+        let backend: Arc<dyn DisplayBackend> = Arc::new(SimBackend::from_file(path).unwrap());
+        monitors::get_monitors(&backend).unwrap()
+    }
+
+    fn apply_scene<'a>(
+        scene: &[Step],
+        monitors: &'a [Monitor],
+        calibration: &Calibration,
+    ) -> Result<Vec<StepResult<'a>>, Box<dyn Error>> {
+        apply(scene, monitors, &Aliases::new(), calibration)
+    }
+
+    fn current(monitor: &Monitor, code: u8) -> u32 {
+        monitor.get_vcp(code).unwrap().current
+    }
+
+    #[test]
+    fn parses_soft_and_hard_off() {
+        let scene = scene(
+            r#"
+            [[scenes.test]]
+            monitors = "1"
+            power = "off"
+
+            [[scenes.test]]
+            monitors = "2"
+            power = "hard-off"
+            "#,
+        );
+
+        let powers: Vec<_> = scene.iter().map(|step| step.power).collect();
+        assert_eq!(powers, [Some(PowerMode::Off), Some(PowerMode::HardOff)]);
+    }
+
+    #[test]
+    fn turns_monitors_on_first_and_off_last() {
+        let scene = scene(
+            r#"
+            [[scenes.test]]
+            monitors = "1"
+            power = "on"
+            brightness = 30
+            input = "hdmi1"
+            vcp = { "0x14" = 5 }
+
+            [[scenes.test]]
+            monitors = "2"
+            power = "off"
+            contrast = "+10"
+            "#,
+        );
+
+        let actions: Vec<Vec<String>> = scene
+            .iter()
+            .map(|step| {
+                let actions = step.actions().unwrap();
+                actions.iter().map(Action::to_string).collect()
+            })
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                vec!["power on", "input hdmi1", "brightness 30", "vcp 0x14 5"],
+                vec!["contrast +10", "power off"],
+            ]
+        );
+    }
+
+    #[test]
+    fn changes_nothing_if_a_step_is_invalid() {
+        let monitors = monitors();
+
+        // The second step has a bad VCP code, or selects a missing monitor.
+        for invalid in &[
+            "monitors = \"2\"\nvcp = { brightness = 10 }",
+            "monitors = \"3\"\nbrightness = 10",
+        ] {
+            let scene = scene(&format!(
+                "[[scenes.test]]\nmonitors = \"1\"\nbrightness = 10\n\n[[scenes.test]]\n{}",
+                invalid
+            ));
+
+            assert!(apply_scene(&scene, &monitors, &Calibration::default()).is_err());
+            assert_eq!(current(&monitors[0], monitors::VCP_BRIGHTNESS), 50);
+        }
+    }
+
+    #[test]
+    fn keeps_going_after_a_failed_action() {
+        let monitors = monitors();
+        let scene = scene(
+            r#"
+            [[scenes.test]]
+            monitors = "all"
+            power = "on"
+            input = "hdmi1"
+            brightness = 30
+            "#,
+        );
+
+        let results = apply_scene(&scene, &monitors, &Calibration::default()).unwrap();
+        let outcomes: Vec<_> = results
+            .iter()
+            .map(|r| (r.monitor.id(), r.action.to_string(), r.result.is_ok()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("sim:1", "power on".to_owned(), true),
+                ("sim:1", "input hdmi1".to_owned(), true),
+                ("sim:1", "brightness 30".to_owned(), true),
+                ("sim:2", "power on".to_owned(), true),
+                ("sim:2", "input hdmi1".to_owned(), false),
+                ("sim:2", "brightness 30".to_owned(), true),
+            ]
+        );
+
+        assert_eq!(monitors[0].power_mode().unwrap(), PowerMode::On);
+        assert_eq!(current(&monitors[1], monitors::VCP_BRIGHTNESS), 30);
+    }
+
+    #[test]
+    fn maps_brightness_through_calibration() {
+        let monitors = monitors();
+        let curve = Curve::try_from(vec![(0, 0), (100, 50)]).unwrap();
+        let selector = "LG".to_owned();
+        let calibration =
+            Calibration::resolve(vec![(&selector, &curve)], &monitors, &Aliases::new()).unwrap();
+
+        let scene = scene(
+            r#"
+            [[scenes.test]]
+            monitors = "all"
+            brightness = 40
+            "#,
+        );
+
+        let results = apply_scene(&scene, &monitors, &calibration).unwrap();
+        let outcomes: Vec<_> = results.into_iter().map(|r| r.result.unwrap()).collect();
+        assert_eq!(outcomes, ["50 -> 40", "50 -> 20"]);
+        assert_eq!(current(&monitors[1], monitors::VCP_BRIGHTNESS), 20);
+    }
+}
@@ -0,0 +1,542 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer};
+
+use crate::monitors::Monitor;
+
+/// Named selectors that can be used in place of a monitor, e.g. `left` or
+/// `desk`, keyed by lowercase name.
+pub type Aliases = HashMap<String, Selector>;
+
+// Guards against aliases that refer to each other.
+const MAX_ALIAS_DEPTH: usize = 8;
+
+/// A set of monitors, written as a comma separated list of terms that are
+/// added to the set in order, or removed from it if prefixed with `!`:
+///
+/// - `all`
+/// - an index (`2`) or range of indices (`1-3`)
+/// - an alias
+/// - a stable id (`DEL-A0B8-ABC123`) or serial number (`ABC123`)
+/// - a name, optionally with `*` and `?` wildcards (`DELL*`)
+///
+/// A selector made up only of removals starts from all monitors, so `!2` is
+/// the same as `all,!2`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Selector {
+    terms: Vec<Term>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Term {
+    negated: bool,
+    kind: TermKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TermKind {
+    All,
+    Range(i32, i32),
+    Any(String),
+    Match(Matcher),
+}
+
+/// Identifies a monitor by the given properties, all of which have to match.
+/// This is what aliases in the config file are made of, e.g.
+/// `left = { serial = "ABC123" }`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Matcher {
+    pub id: Option<String>,
+    pub serial: Option<String>,
+    /// A name pattern, which can contain `*` and `?` wildcards.
+    pub name: Option<String>,
+}
+
+impl Matcher {
+    fn matches(&self, monitor: &Monitor) -> bool {
+        let serial = monitor.edid().and_then(|edid| edid.serial());
+
+        self.id
+            .as_ref()
+            .is_none_or(|id| monitor.id().eq_ignore_ascii_case(id))
+            && self
+                .serial
+                .as_ref()
+                .is_none_or(|s| serial.as_deref() == Some(s.as_str()))
+            && self
+                .name
+                .as_ref()
+                .is_none_or(|name| glob_match(name, monitor.name()))
+    }
+}
+
+impl Selector {
+    /// A selector for the monitors matching `matcher`.
+    pub fn matching(matcher: Matcher) -> Selector {
+        Selector {
+            terms: vec![Term {
+                negated: false,
+                kind: TermKind::Match(matcher),
+            }],
+        }
+    }
+
+    /// Returns the selected monitors, in the order they were enumerated.
+    /// Fails if a term other than a removal doesn't match any monitors, since
+    /// that is usually a typo.
+    pub fn select<'a>(
+        &self,
+        monitors: &'a [Monitor],
+        aliases: &Aliases,
+    ) -> Result<Vec<&'a Monitor>, Box<dyn Error>> {
+        let selected = self.resolve(monitors, aliases, 0)?;
+        Ok(selected.into_iter().map(|i| &monitors[i]).collect())
+    }
+
+    /// Like `select`, but for commands that only make sense for a single
+    /// monitor.
+    pub fn select_one<'a>(
+        &self,
+        monitors: &'a [Monitor],
+        aliases: &Aliases,
+    ) -> Result<&'a Monitor, Box<dyn Error>> {
+        match self.select(monitors, aliases)?.as_slice() {
+            [monitor] => Ok(monitor),
+            selected => {
+                Err(format!("{} matches {} monitors, expected one", self, selected.len()).into())
+            }
+        }
+    }
+
+    fn resolve(
+        &self,
+        monitors: &[Monitor],
+        aliases: &Aliases,
+        depth: usize,
+    ) -> Result<BTreeSet<usize>, Box<dyn Error>> {
+        if depth > MAX_ALIAS_DEPTH {
+            return Err(format!("aliases nested too deeply in {}", self).into());
+        }
+
+        let mut selected = BTreeSet::new();
+
+        if self.terms.iter().all(|term| term.negated) {
+            selected.extend(0..monitors.len());
+        }
+
+        for term in &self.terms {
+            let matched = term.kind.resolve(monitors, aliases, depth)?;
+
+            if term.negated {
+                selected.retain(|i| !matched.contains(i));
+            } else if matched.is_empty() {
+                return Err(format!("no monitor found matching {}", term.kind).into());
+            } else {
+                selected.extend(matched);
+            }
+        }
+
+        Ok(selected)
+    }
+}
+
+impl TermKind {
+    fn resolve(
+        &self,
+        monitors: &[Monitor],
+        aliases: &Aliases,
+        depth: usize,
+    ) -> Result<BTreeSet<usize>, Box<dyn Error>> {
+        let matches = |f: &dyn Fn(&Monitor) -> bool| {
+            monitors
+                .iter()
+                .enumerate()
+                .filter(|(_, m)| f(m))
+                .map(|(i, _)| i)
+                .collect()
+        };
+
+        match self {
+            TermKind::All => Ok((0..monitors.len()).collect()),
+            TermKind::Range(start, end) => Ok(matches(&|m| (*start..=*end).contains(&m.index()))),
+            TermKind::Any(name) => match aliases.get(&name.to_lowercase()) {
+                Some(alias) => alias.resolve(monitors, aliases, depth + 1),
+                None => Ok(matches(&|m| {
+                    m.has_id(name)
+                        || m.edid().and_then(|edid| edid.serial()).as_deref() == Some(name.as_str())
+                        || glob_match(name, m.name())
+                })),
+            },
+            TermKind::Match(matcher) => Ok(matches(&|m| matcher.matches(m))),
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let terms = s
+            .split(',')
+            .map(|term| {
+                let term = term.trim();
+                let (negated, term) = match term.strip_prefix('!') {
+                    Some(term) => (true, term.trim()),
+                    None => (false, term),
+                };
+
+                if term.is_empty() {
+                    return Err(format!("invalid selector: {}", s).into());
+                }
+
+                Ok(Term {
+                    negated,
+                    kind: parse_term(term)?,
+                })
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+
+        Ok(Selector { terms })
+    }
+}
+
+impl<'de> Deserialize<'de> for Selector {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+fn parse_term(term: &str) -> Result<TermKind, Box<dyn Error>> {
+    if term.eq_ignore_ascii_case("all") {
+        return Ok(TermKind::All);
+    }
+
+    if let Ok(index) = term.parse() {
+        return Ok(TermKind::Range(index, index));
+    }
+
+    // Stable ids contain dashes too, so only treat this as a range if both
+    // ends are numbers.
+    if let Some((start, end)) = term.split_once('-') {
+        if let (Ok(start), Ok(end)) = (start.trim().parse(), end.trim().parse()) {
+            if start > end {
+                return Err(format!("invalid range: {}", term).into());
+            }
+            return Ok(TermKind::Range(start, end));
+        }
+    }
+
+    Ok(TermKind::Any(term.to_owned()))
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let terms: Vec<_> = self
+            .terms
+            .iter()
+            .map(|term| {
+                let prefix = if term.negated { "!" } else { "" };
+                format!("{}{}", prefix, term.kind)
+            })
+            .collect();
+        write!(f, "{}", terms.join(","))
+    }
+}
+
+impl fmt::Display for TermKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TermKind::All => write!(f, "all"),
+            TermKind::Range(start, end) if start == end => write!(f, "{}", start),
+            TermKind::Range(start, end) => write!(f, "{}-{}", start, end),
+            TermKind::Any(name) => write!(f, "{}", name),
+            TermKind::Match(matcher) => {
+                let fields: Vec<_> = [
+                    ("id", &matcher.id),
+                    ("serial", &matcher.serial),
+                    ("name", &matcher.name),
+                ]
+                .iter()
+                .filter_map(|(key, value)| value.as_ref().map(|v| format!("{}={}", key, v)))
+                .collect();
+                write!(f, "{{{}}}", fields.join(" "))
+            }
+        }
+    }
+}
+
+/// Matches `text` against a case insensitive pattern where `*` matches any
+/// run of characters and `?` matches any single character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    // Greedy matching that backtracks to the most recent `*`.
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((sp, st)) => {
+                    p = sp + 1;
+                    t = st + 1;
+                    star = Some((sp, st + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::monitors::{self, DisplayBackend, SimBackend};
+
+    fn monitors() -> Vec<Monitor> {
+        let path = monitors::write_fixture(
+            "selector",
+            r#"
+            [[monitors]]
+            id = 1
+            name = "DELL U2720Q"
+            edid = { manufacturer = "DEL", product = 0xa0b8, serial = "ABC123" }
+
+            [[monitors]]
+            id = 2
+            name = "DELL P2419H"
+
+            [[monitors]]
+            id = 3
+            name = "LG ULTRAFINE"
+
+            [[monitors]]
+            id = 4
+            name = "DELL U2720Q"
+            edid = { manufacturer = "DEL", product = 0xa0b8, serial = "XYZ789" }
+            "#,
+        );
+        let backend: Arc<dyn DisplayBackend> = Arc::new(SimBackend::from_file(path).unwrap());
+        monitors::get_monitors(&backend).unwrap()
+    }
+
+    fn aliases(aliases: &[(&str, &str)]) -> Aliases {
+        aliases
+            .iter()
+            .map(|(name, selector)| (name.to_string(), selector.parse().unwrap()))
+            .collect()
+    }
+
+    fn select(monitors: &[Monitor], aliases: &Aliases, selector: &str) -> Vec<i32> {
+        selector
+            .parse::<Selector>()
+            .unwrap()
+            .select(monitors, aliases)
+            .unwrap()
+            .iter()
+            .map(|monitor| monitor.index())
+            .collect()
+    }
+
+    #[test]
+    fn parses_selectors() {
+        for (selector, displayed) in &[
+            ("all", "all"),
+            ("ALL", "all"),
+            ("2", "2"),
+            (" 1 - 3 ", "1-3"),
+            ("1-3, ! 2", "1-3,!2"),
+            ("DEL-A0B8-ABC123", "DEL-A0B8-ABC123"),
+            ("-1", "-1"),
+        ] {
+            let parsed: Selector = selector.parse().unwrap();
+            assert_eq!(parsed.to_string(), *displayed);
+        }
+
+        for selector in &["", " ", "1,,2", "!", "1,", "3-1"] {
+            assert!(
+                selector.parse::<Selector>().is_err(),
+                "{:?} should have been rejected",
+                selector
+            );
+        }
+    }
+
+    #[test]
+    fn selects_ranges() {
+        let monitors = monitors();
+        let aliases = Aliases::new();
+
+        assert_eq!(select(&monitors, &aliases, "2-3"), [2, 3]);
+        assert_eq!(select(&monitors, &aliases, "3,1"), [1, 3]);
+        assert_eq!(select(&monitors, &aliases, "1-2,2-4"), [1, 2, 3, 4]);
+        assert_eq!(select(&monitors, &aliases, "3-9"), [3, 4]);
+        assert_eq!(select(&monitors, &aliases, "all"), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn selects_by_id_serial_and_name() {
+        let monitors = monitors();
+        let aliases = Aliases::new();
+
+        assert_eq!(select(&monitors, &aliases, "del-a0b8-abc123"), [1]);
+        assert_eq!(select(&monitors, &aliases, "sim:2"), [2]);
+        assert_eq!(select(&monitors, &aliases, "XYZ789"), [4]);
+        assert_eq!(select(&monitors, &aliases, "dell u2720q"), [1, 4]);
+        assert_eq!(select(&monitors, &aliases, "DELL*"), [1, 2, 4]);
+        assert_eq!(select(&monitors, &aliases, "*ultra*"), [3]);
+    }
+
+    #[test]
+    fn removes_negated_terms() {
+        let monitors = monitors();
+        let aliases = Aliases::new();
+
+        assert_eq!(select(&monitors, &aliases, "!2"), [1, 3, 4]);
+        assert_eq!(select(&monitors, &aliases, "!2,!DELL*"), [3]);
+        assert_eq!(select(&monitors, &aliases, "all,!1-2"), [3, 4]);
+        assert_eq!(select(&monitors, &aliases, "DELL*,!ABC123"), [2, 4]);
+        // Terms apply in order, so a later term can add a monitor back.
+        assert_eq!(select(&monitors, &aliases, "DELL*,!1,1"), [1, 2, 4]);
+        // Removals don't need to match anything.
+        assert_eq!(select(&monitors, &aliases, "!9"), [1, 2, 3, 4]);
+        assert!(select(&monitors, &aliases, "!all").is_empty());
+    }
+
+    #[test]
+    fn rejects_terms_without_matches() {
+        let monitors = monitors();
+        let aliases = Aliases::new();
+
+        for selector in &["9", "5-9", "1,nope", "SAMSUNG*"] {
+            let selector: Selector = selector.parse().unwrap();
+            assert!(selector.select(&monitors, &aliases).is_err());
+        }
+    }
+
+    #[test]
+    fn selects_exactly_one() {
+        let monitors = monitors();
+        let aliases = Aliases::new();
+
+        let selector: Selector = "LG*".parse().unwrap();
+        assert_eq!(selector.select_one(&monitors, &aliases).unwrap().index(), 3);
+
+        let selector: Selector = "DELL*".parse().unwrap();
+        assert_eq!(
+            selector
+                .select_one(&monitors, &aliases)
+                .map(Monitor::index)
+                .unwrap_err()
+                .to_string(),
+            "DELL* matches 3 monitors, expected one"
+        );
+    }
+
+    #[test]
+    fn resolves_aliases() {
+        let monitors = monitors();
+        let mut aliases = aliases(&[("left", "1"), ("desk", "Left,3"), ("rest", "!desk")]);
+        aliases.insert(
+            "work".to_owned(),
+            Selector::matching(Matcher {
+                serial: Some("XYZ789".to_owned()),
+                name: Some("dell*".to_owned()),
+                ..Matcher::default()
+            }),
+        );
+
+        assert_eq!(select(&monitors, &aliases, "LEFT"), [1]);
+        assert_eq!(select(&monitors, &aliases, "desk"), [1, 3]);
+        assert_eq!(select(&monitors, &aliases, "rest"), [2, 4]);
+        assert_eq!(select(&monitors, &aliases, "work"), [4]);
+        assert_eq!(select(&monitors, &aliases, "all,!work"), [1, 2, 3]);
+    }
+
+    #[test]
+    fn limits_alias_depth() {
+        let monitors = monitors();
+
+        // A chain of aliases, each referring to the next, ending at `1`.
+        let chain = |len: usize| {
+            let mut aliases = Aliases::new();
+            for i in 0..len {
+                let target = if i + 1 == len {
+                    "1".to_owned()
+                } else {
+                    format!("a{}", i + 1)
+                };
+                aliases.insert(format!("a{}", i), target.parse().unwrap());
+            }
+            aliases
+        };
+
+        let selector: Selector = "a0".parse().unwrap();
+        assert!(selector.select(&monitors, &chain(MAX_ALIAS_DEPTH)).is_ok());
+        assert!(selector
+            .select(&monitors, &chain(MAX_ALIAS_DEPTH + 1))
+            .is_err());
+
+        let aliases = aliases(&[("loop", "1,loop")]);
+        let selector: Selector = "loop".parse().unwrap();
+        assert!(selector
+            .select(&monitors, &aliases)
+            .map(|selected| selected.len())
+            .unwrap_err()
+            .to_string()
+            .starts_with("aliases nested too deeply"));
+    }
+
+    #[test]
+    fn matches_globs() {
+        for (pattern, text, matches) in &[
+            ("", "", true),
+            ("", "a", false),
+            ("*", "", true),
+            ("?", "", false),
+            ("DELL*", "dell u2720q", true),
+            ("dell?u2720q", "DELL U2720Q", true),
+            ("dell", "dell u2720q", false),
+            ("*q", "dell u2720q", true),
+            ("*U27*", "dell u2720q", true),
+            ("**", "abc", true),
+            ("a*?", "a", false),
+            ("a*?", "ab", true),
+            // A `*` has to give back what it swallowed when the rest of the
+            // pattern doesn't match.
+            ("*abc", "ababc", true),
+            ("a*b*c", "abxbxc", true),
+            ("a*bc", "abcbd", false),
+            ("*a*b", "xaxxbxb", true),
+            ("*aab", "aaaab", true),
+            ("é*", "ÉCRAN", true),
+        ] {
+            assert_eq!(
+                glob_match(pattern, text),
+                *matches,
+                "{:?} against {:?}",
+                pattern,
+                text
+            );
+        }
+    }
+}
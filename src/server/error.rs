@@ -0,0 +1,88 @@
+use std::error::Error;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::monitors::{Monitor, MonitorError};
+
+/// Identifies the kind of failure to clients. The names are part of the
+/// protocol, so shouldn't change.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request couldn't be parsed, or doesn't make sense.
+    BadRequest,
+    /// The request refers to a monitor that isn't connected.
+    UnknownMonitor,
+    /// Talking to the monitor failed, which may work if retried.
+    DdcFailure,
+    /// The monitor doesn't support what was asked of it.
+    UnsupportedFeature,
+}
+
+/// A failed request, as reported to the client.
+#[derive(Debug)]
+pub struct ServerError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::UnknownMonitor => "unknown_monitor",
+            ErrorCode::DdcFailure => "ddc_failure",
+            ErrorCode::UnsupportedFeature => "unsupported_feature",
+        }
+    }
+}
+
+impl ServerError {
+    pub fn new(code: ErrorCode, message: impl ToString) -> ServerError {
+        ServerError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn bad_request(message: impl ToString) -> ServerError {
+        ServerError::new(ErrorCode::BadRequest, message)
+    }
+
+    /// An error from doing something to a monitor, e.g. "set power mode".
+    pub fn monitor(monitor: &Monitor, action: &str, e: MonitorError) -> ServerError {
+        ServerError::new(
+            code_for(&e),
+            format!("failed to {} of monitor {}: {}", action, monitor.id(), e),
+        )
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code.as_str(), self.message)
+    }
+}
+
+impl Error for ServerError {}
+
+impl From<MonitorError> for ServerError {
+    fn from(e: MonitorError) -> Self {
+        ServerError::new(code_for(&e), e)
+    }
+}
+
+fn code_for(e: &MonitorError) -> ErrorCode {
+    match e {
+        MonitorError::NotFound(_) => ErrorCode::UnknownMonitor,
+        MonitorError::DdcUnsupported | MonitorError::UnsupportedFeature(_) => {
+            ErrorCode::UnsupportedFeature
+        }
+        MonitorError::InvalidValue(_) => ErrorCode::BadRequest,
+        MonitorError::Timeout
+        | MonitorError::Checksum
+        | MonitorError::Protocol(_)
+        | MonitorError::Os { .. } => ErrorCode::DdcFailure,
+    }
+}
@@ -0,0 +1,360 @@
+//! Pushes changes to monitors to subscribed clients, so that they don't have
+//! to keep asking for the full list. The server polls the monitors on their
+//! behalf, and only while someone is subscribed, so the DDC traffic is the
+//! same however many clients there are. Clients that go away quietly are
+//! noticed within a `HEARTBEAT`, rather than on the next change.
+//!
+//! Events are JSON objects tagged by `type`, sent a line at a time over a
+//! TCP stream, or a message at a time over a WebSocket:
+//!
+//! ```json
+//! { "type": "brightness", "monitor": "GSM-5B09-1234", "brightness": 40 }
+//! ```
+
+use std::error::Error;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::ops::Deref;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::protocol::MonitorState;
+use super::Server;
+use crate::monitors::Monitor;
+
+/// How long a client can go without being sent anything before checking
+/// that it's still there.
+pub const HEARTBEAT: Duration = Duration::from_secs(10);
+
+/// How often the poller looks for monitors that have been plugged in or out,
+/// which takes a lot longer than checking their settings.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Power {
+        monitor: String,
+        power: Option<String>,
+    },
+    Brightness {
+        monitor: String,
+        brightness: Option<u32>,
+    },
+    Input {
+        monitor: String,
+        input: Option<String>,
+    },
+    Added {
+        monitor: String,
+        state: MonitorState,
+    },
+    Removed {
+        monitor: String,
+    },
+    RefreshCompleted,
+}
+
+/// Receives events until dropped.
+pub struct Subscription {
+    receiver: Receiver<Arc<Event>>,
+    // Only the subscription holds a strong reference, so `Events` can tell
+    // when it's been dropped without having to send it anything.
+    _alive: Arc<()>,
+}
+
+impl Deref for Subscription {
+    type Target = Receiver<Arc<Event>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.receiver
+    }
+}
+
+struct Subscriber {
+    sender: Sender<Arc<Event>>,
+    alive: Weak<()>,
+}
+
+/// Tracks subscribers, and the monitors as they were last seen.
+#[derive(Default)]
+pub struct Events {
+    subscribers: Mutex<Vec<Subscriber>>,
+    /// `None` while nobody is subscribed, as the monitors aren't polled then.
+    last: Mutex<Option<Vec<MonitorState>>>,
+}
+
+impl Events {
+    pub fn subscribe(&self) -> Subscription {
+        let (sender, receiver) = mpsc::channel();
+        let alive = Arc::new(());
+        self.subscribers.lock().unwrap().push(Subscriber {
+            sender,
+            alive: Arc::downgrade(&alive),
+        });
+        Subscription {
+            receiver,
+            _alive: alive,
+        }
+    }
+
+    /// Whether anyone is still subscribed, forgetting those that have gone.
+    pub fn has_subscribers(&self) -> bool {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| subscriber.alive.strong_count() > 0);
+        !subscribers.is_empty()
+    }
+
+    /// Sends an event to every subscriber, forgetting those that have gone.
+    pub fn publish(&self, event: Event) {
+        log::debug!("publishing event: {:?}", event);
+        let event = Arc::new(event);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.sender.send(event.clone()).is_ok());
+    }
+
+    /// Reads the state of the monitors and publishes how it has changed
+    /// since the last update.
+    pub fn update(&self, monitors: &[Monitor]) {
+        let mut last = self.last.lock().unwrap();
+
+        if !self.has_subscribers() {
+            *last = None;
+            return;
+        }
+
+        let mut states: Vec<_> = monitors.iter().map(MonitorState::new).collect();
+
+        if let Some(last) = last.as_ref() {
+            // A setting that can't be read right now is most likely a
+            // transient DDC failure, so it keeps its last known value rather
+            // than being reported as a change.
+            for state in &mut states {
+                if let Some(old) = last.iter().find(|old| old.id == state.id) {
+                    state.power = state.power.take().or_else(|| old.power.clone());
+                    state.brightness = state.brightness.or(old.brightness);
+                    state.max_brightness = state.max_brightness.or(old.max_brightness);
+                    state.input = state.input.take().or_else(|| old.input.clone());
+                }
+            }
+
+            for event in diff(last, &states) {
+                self.publish(event);
+            }
+        }
+
+        *last = Some(states);
+    }
+}
+
+/// Works out the events that turn `old` into `new`.
+fn diff(old: &[MonitorState], new: &[MonitorState]) -> Vec<Event> {
+    let mut events = vec![];
+
+    for state in new {
+        let old = match old.iter().find(|old| old.id == state.id) {
+            Some(old) => old,
+            None => {
+                events.push(Event::Added {
+                    monitor: state.id.clone(),
+                    state: state.clone(),
+                });
+                continue;
+            }
+        };
+
+        if state.power != old.power {
+            events.push(Event::Power {
+                monitor: state.id.clone(),
+                power: state.power.clone(),
+            });
+        }
+
+        if state.brightness != old.brightness {
+            events.push(Event::Brightness {
+                monitor: state.id.clone(),
+                brightness: state.brightness,
+            });
+        }
+
+        if state.input != old.input {
+            events.push(Event::Input {
+                monitor: state.id.clone(),
+                input: state.input.clone(),
+            });
+        }
+    }
+
+    for state in old {
+        if !new.iter().any(|new| new.id == state.id) {
+            events.push(Event::Removed {
+                monitor: state.id.clone(),
+            });
+        }
+    }
+
+    events
+}
+
+/// Starts polling the monitors for changes on a new thread, and now and then
+/// for monitors that have been plugged in or out.
+pub fn spawn_poller(server: Arc<Mutex<Server>>, interval: Duration) {
+    thread::spawn(move || {
+        let mut last_scan = Instant::now();
+        loop {
+            thread::sleep(interval);
+
+            let mut server = server.lock().unwrap();
+            if last_scan.elapsed() >= RESCAN_INTERVAL {
+                server.rescan();
+                last_scan = Instant::now();
+            } else {
+                server.poll_events();
+            }
+        }
+    });
+}
+
+/// Starts streaming events to TCP clients on a new thread.
+pub fn spawn(addr: SocketAddr, events: Arc<Events>) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(addr)?;
+
+    log::info!("event stream running on {}...", addr);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let subscription = events.subscribe();
+                    thread::spawn(move || stream_events(stream, subscription));
+                }
+                Err(e) => log::warn!("failed to accept event stream client: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn stream_events(mut stream: TcpStream, subscription: Subscription) {
+    let peer = stream.peer_addr().ok();
+    log::debug!("event stream client connected: {:?}", peer);
+
+    loop {
+        let event = match subscription.recv_timeout(HEARTBEAT) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => match has_closed(&stream) {
+                Ok(false) => continue,
+                _ => break,
+            },
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        let mut line = serde_json::to_vec(&*event).unwrap();
+        line.push(b'\n');
+
+        if stream.write_all(&line).is_err() {
+            break;
+        }
+    }
+
+    log::debug!("event stream client disconnected: {:?}", peer);
+}
+
+/// Whether the client has closed its end. Clients aren't expected to send
+/// anything, so the stream is only read from to see whether it has ended.
+fn has_closed(stream: &TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let result = match stream.peek(&mut [0]) {
+        Ok(read) => Ok(read == 0),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    };
+    stream.set_nonblocking(false)?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::monitors::{self, PowerMode, SimBackend, VCP_BRIGHTNESS};
+
+    fn db() -> Db {
+        let path = monitors::write_fixture(
+            "events",
+            r#"
+            [[monitors]]
+            id = 1
+            name = "Dell"
+            vcp = { "0x10" = 50 }
+            "#,
+        );
+        Db::new(Arc::new(SimBackend::from_file(path).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn publishes_changes() {
+        let db = db();
+        let events = Events::default();
+        let subscription = events.subscribe();
+
+        events.update(db.monitors());
+        assert!(subscription.try_recv().is_err());
+
+        db.monitors()[0].set_vcp(VCP_BRIGHTNESS, 30).unwrap();
+        db.monitors()[0].set_power_mode(PowerMode::Off).unwrap();
+        events.update(db.monitors());
+
+        let published: Vec<_> = subscription
+            .try_iter()
+            .map(|event| serde_json::to_value(&*event).unwrap())
+            .collect();
+        assert_eq!(
+            published,
+            [
+                serde_json::json!({ "type": "power", "monitor": "sim:1", "power": "off" }),
+                serde_json::json!({ "type": "brightness", "monitor": "sim:1", "brightness": 30 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_polling_once_subscribers_go() {
+        let db = db();
+        let events = Events::default();
+
+        let subscription = events.subscribe();
+        events.update(db.monitors());
+        assert!(events.last.lock().unwrap().is_some());
+
+        // Nothing has been published, so only dropping the subscription
+        // tells that it's gone.
+        drop(subscription);
+        events.update(db.monitors());
+        assert!(events.last.lock().unwrap().is_none());
+        assert!(events.subscribers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn notices_closed_streams() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        assert!(!has_closed(&stream).unwrap());
+
+        drop(client);
+        let start = Instant::now();
+        while !has_closed(&stream).unwrap() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,565 @@
+//! An HTTP API over the same state as the UDP server:
+//!
+//! - `GET /monitors`
+//! - `GET /monitors/{id}`
+//! - `PUT /monitors/{id}/power` with `{ "mode": "off", "fade": "2s" }`
+//! - `GET /monitors/{id}/vcp/{code}`
+//! - `PUT /monitors/{id}/vcp/{code}` with `{ "value": 40 }`
+//! - `POST /refresh`
+//! - `GET /events`, upgraded to a WebSocket that events are pushed over
+//!
+//! `{id}` is anything that selects monitors on the command line. Requests
+//! that read from a monitor need it to select exactly one.
+
+use std::error::Error;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use super::events::{Subscription, HEARTBEAT};
+use super::protocol::MonitorState;
+use super::{ErrorCode, Server, ServerError};
+use crate::fade;
+use crate::monitors::{self, PowerMode};
+use crate::selector::Selector;
+
+// Request bodies are small JSON objects, so anything bigger is a mistake.
+const MAX_BODY: u64 = 64 * 1024;
+
+#[derive(Deserialize)]
+struct PowerRequest {
+    mode: PowerMode,
+    fade: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct VcpRequest {
+    value: u32,
+}
+
+#[derive(Serialize)]
+struct VcpResponse {
+    current: u32,
+    max: u32,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    code: ErrorCode,
+    message: String,
+}
+
+/// What a handler replies with, before it is turned into a response.
+enum Reply {
+    Json(Vec<u8>),
+    NoContent,
+}
+
+/// Starts serving the API on a new thread.
+pub fn spawn(addr: SocketAddr, server: Arc<Mutex<Server>>) -> Result<(), Box<dyn Error>> {
+    let http = tiny_http::Server::http(addr)
+        .map_err(|e| format!("failed to listen on {}: {}", addr, e))?;
+
+    log::info!("http api running on {}...", addr);
+
+    thread::spawn(move || serve(&http, &server));
+
+    Ok(())
+}
+
+fn serve(http: &tiny_http::Server, server: &Mutex<Server>) {
+    for request in http.incoming_requests() {
+        handle(server, request);
+    }
+}
+
+fn handle(server: &Mutex<Server>, mut request: Request) {
+    log::debug!("{} {}", request.method(), request.url());
+
+    if request.url() == "/events" {
+        if let Some(key) = websocket_key(&request) {
+            let subscription = server.lock().unwrap().events.subscribe();
+            return subscribe(request, &key, subscription);
+        }
+    }
+
+    let response = match route(server, &mut request) {
+        Ok(Reply::Json(body)) => json_response(200, body),
+        Ok(Reply::NoContent) => Response::from_data(vec![]).with_status_code(204),
+        Err((status, e)) => {
+            log::error!("{} {} failed: {}", request.method(), request.url(), e);
+            let body = serde_json::to_vec(&ErrorResponse {
+                code: e.code,
+                message: e.message,
+            })
+            .unwrap();
+            json_response(status, body)
+        }
+    };
+
+    if let Err(e) = request.respond(response) {
+        log::warn!("failed to send http response: {}", e);
+    }
+}
+
+fn route(server: &Mutex<Server>, request: &mut Request) -> Result<Reply, (u16, ServerError)> {
+    let path = request.url().split('?').next().unwrap_or("");
+    let segments: Vec<String> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(percent_decode)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let method = request.method().clone();
+
+    match (&method, segments.as_slice()) {
+        (Method::Get, ["monitors"]) => {
+            let server = server.lock().unwrap();
+            let monitors: Vec<_> = server.monitors().iter().map(MonitorState::new).collect();
+            json(&monitors)
+        }
+        (Method::Get, ["monitors", id]) => {
+            let selector = selector(id)?;
+            let server = server.lock().unwrap();
+            json(&MonitorState::new(
+                server.monitor(&selector).map_err(with_status)?,
+            ))
+        }
+        (Method::Put, ["monitors", id, "power"]) => {
+            let selector = selector(id)?;
+            let body: PowerRequest = read_json(request)?;
+            let fade = body
+                .fade
+                .map(|fade| fade::parse_duration(&fade))
+                .transpose()
+                .map_err(bad_request)?;
+            server
+                .lock()
+                .unwrap()
+                .set_power_mode(&selector, body.mode, fade)
+                .map(|_| Reply::NoContent)
+                .map_err(with_status)
+        }
+        (Method::Get, ["monitors", id, "vcp", code]) => {
+            let selector = selector(id)?;
+            let code = vcp_code(code)?;
+            let value = server.lock().unwrap().get_vcp(&selector, code);
+            value.map_err(with_status).and_then(|value| {
+                json(&VcpResponse {
+                    current: value.current,
+                    max: value.max,
+                })
+            })
+        }
+        (Method::Put, ["monitors", id, "vcp", code]) => {
+            let selector = selector(id)?;
+            let code = vcp_code(code)?;
+            let body: VcpRequest = read_json(request)?;
+            server
+                .lock()
+                .unwrap()
+                .set_vcp(&selector, code, body.value)
+                .map(|_| Reply::NoContent)
+                .map_err(with_status)
+        }
+        (Method::Post, ["refresh"]) => server
+            .lock()
+            .unwrap()
+            .refresh()
+            .map(|_| Reply::NoContent)
+            .map_err(with_status),
+        (Method::Get, ["events"]) => Err(bad_request("events are only sent over a websocket")),
+        (_, ["events"])
+        | (_, ["monitors"])
+        | (_, ["monitors", _])
+        | (_, ["monitors", _, "power"])
+        | (_, ["monitors", _, "vcp", _])
+        | (_, ["refresh"]) => Err((
+            405,
+            ServerError::bad_request(format!("method not allowed: {}", method)),
+        )),
+        _ => Err((
+            404,
+            ServerError::bad_request(format!("not found: {}", path)),
+        )),
+    }
+}
+
+/// The key of a request to open a WebSocket, if that's what it is.
+fn websocket_key(request: &Request) -> Option<String> {
+    let upgrade = header(request, "Upgrade")?;
+    if *request.method() != Method::Get || !upgrade.eq_ignore_ascii_case("websocket") {
+        return None;
+    }
+
+    header(request, "Sec-WebSocket-Key").map(str::to_owned)
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// Takes over the connection as a WebSocket, and sends events over it on a
+/// new thread until the client goes away.
+fn subscribe(request: Request, key: &str, subscription: Subscription) {
+    let accept = Header::from_bytes(
+        &b"Sec-WebSocket-Accept"[..],
+        derive_accept_key(key.as_bytes()),
+    )
+    .unwrap();
+    let stream = request.upgrade("websocket", Response::empty(101).with_header(accept));
+
+    thread::spawn(move || {
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+        loop {
+            // The upgraded stream can't be read from without blocking the
+            // sends, so a client that has gone is noticed by pinging it until
+            // the connection fails.
+            let message = match subscription.recv_timeout(HEARTBEAT) {
+                Ok(event) => Message::Text(serde_json::to_string(&*event).unwrap()),
+                Err(RecvTimeoutError::Timeout) => Message::Ping(vec![]),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            if socket.send(message).is_err() {
+                break;
+            }
+        }
+
+        log::debug!("websocket client disconnected");
+    });
+}
+
+fn selector(id: &str) -> Result<Selector, (u16, ServerError)> {
+    id.parse().map_err(bad_request)
+}
+
+fn vcp_code(code: &str) -> Result<u8, (u16, ServerError)> {
+    monitors::parse_vcp_code(code).map_err(bad_request)
+}
+
+fn read_json<T: DeserializeOwned>(request: &mut Request) -> Result<T, (u16, ServerError)> {
+    let mut body = vec![];
+    request
+        .as_reader()
+        .take(MAX_BODY)
+        .read_to_end(&mut body)
+        .map_err(|e| bad_request(format!("failed to read request body: {}", e)))?;
+
+    serde_json::from_slice(&body).map_err(|e| bad_request(format!("invalid request body: {}", e)))
+}
+
+fn json<T: Serialize>(value: &T) -> Result<Reply, (u16, ServerError)> {
+    Ok(Reply::Json(serde_json::to_vec(value).unwrap()))
+}
+
+fn json_response(status: u16, body: Vec<u8>) -> Response<std::io::Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+fn bad_request(e: impl ToString) -> (u16, ServerError) {
+    (400, ServerError::bad_request(e))
+}
+
+fn with_status(e: ServerError) -> (u16, ServerError) {
+    let status = match e.code {
+        ErrorCode::BadRequest => 400,
+        ErrorCode::UnknownMonitor => 404,
+        ErrorCode::UnsupportedFeature => 422,
+        // The monitor is to the server what an upstream server is to a
+        // gateway.
+        ErrorCode::DdcFailure => 502,
+    };
+    (status, e)
+}
+
+/// Decodes `%XX` escapes, which ids need for characters such as `/`.
+/// Anything that isn't a complete escape is left as it is.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        // `from_str_radix` allows a leading sign, so the digits are checked
+        // first.
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::config::Config;
+    use crate::monitors::{
+        DisplayBackend, Handle, MonitorError, MonitorInfo, SimBackend, VcpValue,
+    };
+
+    /// A monitor that is connected, but never answers.
+    struct Unresponsive;
+
+    impl DisplayBackend for Unresponsive {
+        fn enumerate(&self) -> Result<Vec<MonitorInfo>, MonitorError> {
+            Ok(vec![MonitorInfo {
+                name: "Dell".to_owned(),
+                handle: Handle(0),
+                edid: None,
+                path: "test:1".to_owned(),
+            }])
+        }
+
+        fn get_vcp(&self, _handle: Handle, _code: u8) -> Result<VcpValue, MonitorError> {
+            Err(MonitorError::Timeout)
+        }
+
+        fn set_vcp(&self, _handle: Handle, _code: u8, _value: u32) -> Result<(), MonitorError> {
+            Err(MonitorError::Timeout)
+        }
+
+        fn capabilities(&self, _handle: Handle) -> Result<String, MonitorError> {
+            Err(MonitorError::Timeout)
+        }
+    }
+
+    /// Serves the API on a free port, returning its address.
+    fn start(backend: Arc<dyn DisplayBackend>) -> SocketAddr {
+        let server = Mutex::new(Server::new(backend, Config::default()).unwrap());
+        let http = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let addr = http.server_addr().to_ip().unwrap();
+        thread::spawn(move || serve(&http, &server));
+        addr
+    }
+
+    fn sim() -> SocketAddr {
+        let path = monitors::write_fixture(
+            "http",
+            r#"
+            [[monitors]]
+            id = 1
+            name = "Dell"
+            vcp = { "0x10" = 50 }
+
+            [[monitors]]
+            id = 2
+            name = "LG"
+            "#,
+        );
+        start(Arc::new(SimBackend::from_file(path).unwrap()))
+    }
+
+    /// Sends a request, returning the status and the JSON body, if any.
+    fn send(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        let body = if body.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(body).unwrap()
+        };
+        (status, body)
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> (u16, Value) {
+        send(addr, "GET", path, "")
+    }
+
+    fn put(addr: SocketAddr, path: &str, body: &str) -> (u16, Value) {
+        send(addr, "PUT", path, body)
+    }
+
+    #[test]
+    fn lists_and_shows_monitors() {
+        let addr = sim();
+
+        let (status, monitors) = get(addr, "/monitors");
+        assert_eq!(status, 200);
+        assert_eq!(monitors[0]["id"], "sim:1");
+        assert_eq!(monitors[0]["brightness"], 50);
+        assert_eq!(monitors[1]["name"], "LG");
+
+        let (status, monitor) = get(addr, "/monitors/sim%3A2?ignored=1");
+        assert_eq!(status, 200);
+        assert_eq!(monitor["name"], "LG");
+
+        let (status, monitor) = get(addr, "/monitors/Dell");
+        assert_eq!(status, 200);
+        assert_eq!(monitor["index"], 1);
+    }
+
+    #[test]
+    fn routes_requests() {
+        let addr = sim();
+
+        assert_eq!(get(addr, "/").0, 404);
+        assert_eq!(get(addr, "/monitors/1/contrast").0, 404);
+        assert_eq!(get(addr, "/monitors/1/vcp").0, 404);
+        assert_eq!(send(addr, "DELETE", "/monitors", "").0, 405);
+        assert_eq!(send(addr, "POST", "/monitors/1/power", "").0, 405);
+        assert_eq!(get(addr, "/refresh").0, 405);
+        assert_eq!(send(addr, "POST", "/refresh", "").0, 204);
+
+        // Without the upgrade headers, there's no WebSocket to send over.
+        let (status, error) = get(addr, "/events");
+        assert_eq!(status, 400);
+        assert_eq!(error["code"], "bad_request");
+    }
+
+    #[test]
+    fn maps_errors_to_statuses() {
+        let addr = sim();
+
+        let (status, error) = get(addr, "/monitors/9");
+        assert_eq!(status, 404);
+        assert_eq!(error["code"], "unknown_monitor");
+
+        // Reading needs a single monitor.
+        let (status, error) = get(addr, "/monitors/all");
+        assert_eq!(status, 400);
+        assert_eq!(error["code"], "bad_request");
+
+        assert_eq!(get(addr, "/monitors/1,,2").0, 400);
+
+        let (status, error) = get(addr, "/monitors/1/vcp/0x12");
+        assert_eq!(status, 422);
+        assert_eq!(error["code"], "unsupported_feature");
+
+        let addr = start(Arc::new(Unresponsive));
+        let (status, error) = get(addr, "/monitors/1/vcp/0x10");
+        assert_eq!(status, 502);
+        assert_eq!(error["code"], "ddc_failure");
+        assert_eq!(
+            put(addr, "/monitors/1/vcp/0x10", r#"{ "value": 1 }"#).0,
+            502
+        );
+    }
+
+    #[test]
+    fn sets_power_modes() {
+        let addr = sim();
+        let power = || get(addr, "/monitors/1").1["power"].clone();
+
+        assert_eq!(
+            put(addr, "/monitors/1/power", r#"{ "mode": "off" }"#).0,
+            204
+        );
+        assert_eq!(power(), "off");
+        assert_eq!(
+            put(addr, "/monitors/1/power", r#"{ "mode": "hard-off" }"#).0,
+            204
+        );
+        assert_eq!(power(), "hard-off");
+        assert_eq!(
+            put(addr, "/monitors/all/power", r#"{ "mode": "on" }"#).0,
+            204
+        );
+        assert_eq!(power(), "on");
+        assert_eq!(get(addr, "/monitors/2").1["power"], "on");
+
+        for body in &[
+            "",
+            "off",
+            "{}",
+            r#"{ "mode": "sideways" }"#,
+            r#"{ "mode": "off", "fade": "forever" }"#,
+        ] {
+            let (status, error) = put(addr, "/monitors/1/power", body);
+            assert_eq!(status, 400, "{:?} should have been rejected", body);
+            assert_eq!(error["code"], "bad_request");
+        }
+        assert_eq!(power(), "on");
+    }
+
+    #[test]
+    fn reads_and_writes_vcp_features() {
+        let addr = sim();
+
+        assert_eq!(
+            put(addr, "/monitors/1/vcp/0x10", r#"{ "value": 30 }"#).0,
+            204
+        );
+        assert_eq!(
+            get(addr, "/monitors/1/vcp/0x10"),
+            (200, json!({ "current": 30, "max": 100 }))
+        );
+        assert_eq!(get(addr, "/monitors/1/vcp/16").1["current"], 30);
+
+        for (code, body) in &[
+            ("0x10", r#"{ "value": 500 }"#),
+            ("0x10", r#"{ "value": -1 }"#),
+            ("0x10", r#"{ "level": 30 }"#),
+            ("zz", r#"{ "value": 30 }"#),
+            ("0x100", r#"{ "value": 30 }"#),
+        ] {
+            let path = format!("/monitors/1/vcp/{}", code);
+            assert_eq!(put(addr, &path, body).0, 400, "{} {}", code, body);
+        }
+        assert_eq!(get(addr, "/monitors/1/vcp/0x10").1["current"], 30);
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("sim%3A1"), "sim:1");
+        assert_eq!(percent_decode("a%2fb%2Fc"), "a/b/c");
+        assert_eq!(percent_decode("%e2%9c%93"), "\u{2713}");
+        assert_eq!(percent_decode("%%41"), "%A");
+        assert_eq!(percent_decode("plain"), "plain");
+
+        for malformed in &["%+1", "%-1", "% 1", "%1", "%", "%zz", "%1g"] {
+            assert_eq!(percent_decode(malformed), *malformed);
+        }
+    }
+}
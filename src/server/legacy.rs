@@ -0,0 +1,324 @@
+//! The original `cmd:arg,arg` text protocol, kept for existing clients.
+//! Failures are replied to with `error;<code>;<message>`, which still starts
+//! with the `error` that older clients look for.
+
+use std::time::Duration;
+
+use super::{Server, ServerError};
+use crate::fade;
+use crate::monitors::{self, PowerMode};
+use crate::selector::Selector;
+
+/// Handles a text message, returning the reply.
+pub fn handle(server: &mut Server, message: &[u8]) -> Vec<u8> {
+    let message = String::from_utf8_lossy(message);
+
+    log::debug!("received: {}", message);
+
+    let res = match parse_message(&message) {
+        Some((cmd, args)) => dispatch(server, cmd, args),
+        None => Err(ServerError::bad_request(format!(
+            "invalid message format: {}",
+            message
+        ))),
+    };
+
+    match res {
+        Ok(response) => response.into_bytes(),
+        Err(e) => {
+            log::error!("{}", e);
+            format!("error;{};{}", e.code.as_str(), e.message).into_bytes()
+        }
+    }
+}
+
+fn dispatch(server: &mut Server, cmd: &str, args: Vec<&str>) -> Result<String, ServerError> {
+    match cmd {
+        "list" => list(server),
+        "set" => set_power_mode(server, args),
+        "brightness" => set_level(server, monitors::VCP_BRIGHTNESS, args),
+        "contrast" => set_level(server, monitors::VCP_CONTRAST, args),
+        "volume" => set_level(server, monitors::VCP_VOLUME, args),
+        "mute" => set_mute(server, args),
+        "refresh" => refresh(server),
+        "scene" => apply_scene(server, args),
+        _ => Err(ServerError::bad_request(format!(
+            "invalid command: {}({})",
+            cmd,
+            args.join(",")
+        ))),
+    }
+}
+
+fn list(server: &Server) -> Result<String, ServerError> {
+    let mut response = String::new();
+
+    for monitor in server.monitors() {
+        let mode = match monitor.power_mode() {
+            Ok(mode) => encode_power_mode(mode),
+            Err(e) => {
+                log::warn!(
+                    "failed to get power mode of monitor {}: {}",
+                    monitor.id(),
+                    e
+                );
+                '0'
+            }
+        };
+
+        // The EDID fields and stable id follow the original three, so clients
+        // that only know about those can ignore them. The first field stays
+        // the index, which is what those clients pass back to `set`.
+        let (manufacturer, product, serial, year) = match monitor.edid() {
+            Some(edid) => (
+                edid.manufacturer.clone(),
+                format!("{:04X}", edid.product),
+                edid.serial().unwrap_or_default(),
+                edid.year.to_string(),
+            ),
+            None => Default::default(),
+        };
+
+        response.push_str(&format!(
+            "{};{};{};{};{};{};{};{}\n",
+            monitor.index(),
+            monitor.name(),
+            mode,
+            manufacturer,
+            product,
+            serial,
+            year,
+            monitor.id()
+        ));
+    }
+
+    Ok(response)
+}
+
+/// `set:<selector>,<mode>[,<fade>]`.
+fn set_power_mode(server: &Server, args: Vec<&str>) -> Result<String, ServerError> {
+    let (selector, mode, fade) = parse_target_args(&args)?;
+    let mode = decode_power_mode(mode)
+        .ok_or_else(|| ServerError::bad_request(format!("invalid power mode: {}", mode)))?;
+
+    server.set_power_mode(&selector, mode, fade)?;
+    Ok("ok".to_owned())
+}
+
+/// `brightness:<selector>,<value>[,<fade>]`, and the same for contrast and
+/// volume. The value is anything `mona brightness` accepts, e.g. `40`, `40%`
+/// or `+10`.
+fn set_level(server: &Server, code: u8, args: Vec<&str>) -> Result<String, ServerError> {
+    let (selector, value, fade) = parse_target_args(&args)?;
+    let adjustment = value.parse().map_err(ServerError::bad_request)?;
+
+    server.set_level(&selector, code, adjustment, fade)?;
+    Ok("ok".to_owned())
+}
+
+/// `mute:<selector>,<on|off|toggle>`.
+fn set_mute(server: &Server, args: Vec<&str>) -> Result<String, ServerError> {
+    let (state, selector) = match args.split_last() {
+        Some((state, selector)) if !selector.is_empty() => (*state, selector.join(",")),
+        _ => return Err(invalid_arguments(&args)),
+    };
+
+    let selector = selector.parse().map_err(ServerError::bad_request)?;
+    let state = state.parse().map_err(ServerError::bad_request)?;
+
+    server.set_mute(&selector, state)?;
+    Ok("ok".to_owned())
+}
+
+/// Replies with a line per action: `index;action;ok|error:<message>`.
+fn apply_scene(server: &Server, args: Vec<&str>) -> Result<String, ServerError> {
+    let name = match args.as_slice() {
+        [name] => name,
+        _ => return Err(invalid_arguments(&args)),
+    };
+
+    let mut response = String::new();
+
+    for step in server.apply_scene(name)? {
+        let result = match step.result {
+            Ok(_) => "ok".to_owned(),
+            Err(e) => format!("error:{}", e),
+        };
+
+        response.push_str(&format!(
+            "{};{};{}\n",
+            step.monitor.index(),
+            step.action,
+            result
+        ));
+    }
+
+    Ok(response)
+}
+
+fn refresh(server: &mut Server) -> Result<String, ServerError> {
+    server.refresh()?;
+    Ok("ok".to_owned())
+}
+
+/// Splits `<selector>,<value>[,<fade>]`. The selector can contain commas
+/// itself, so the value comes last, or second to last if followed by a fade
+/// duration (which always has a unit, so can't be mistaken for a value).
+fn parse_target_args<'a>(
+    args: &[&'a str],
+) -> Result<(Selector, &'a str, Option<Duration>), ServerError> {
+    let (fade, args) = match args.split_last() {
+        Some((last, rest)) => match fade::parse_duration(last) {
+            Ok(duration) => (Some(duration), rest),
+            Err(_) => (None, args),
+        },
+        None => (None, args),
+    };
+
+    match args.split_last() {
+        Some((value, selector)) if !selector.is_empty() => {
+            let selector = selector
+                .join(",")
+                .parse()
+                .map_err(ServerError::bad_request)?;
+            Ok((selector, value, fade))
+        }
+        _ => Err(invalid_arguments(args)),
+    }
+}
+
+fn invalid_arguments(args: &[&str]) -> ServerError {
+    ServerError::bad_request(format!(
+        "invalid arguments ({}): {}",
+        args.len(),
+        args.join(",")
+    ))
+}
+
+// `1` and `2` predate the full set of power modes. `1` is reported for any
+// off state, so that existing clients still recognise it, and turns the
+// monitor off the same way as `off` everywhere else. `5` is hard off, as the
+// same value is in MCCS.
+
+fn encode_power_mode(mode: PowerMode) -> char {
+    match mode {
+        PowerMode::Off | PowerMode::HardOff => '1',
+        PowerMode::On => '2',
+        PowerMode::Standby => '3',
+        PowerMode::Suspend => '4',
+        PowerMode::Unknown(_) => '0',
+    }
+}
+
+fn decode_power_mode(value: &str) -> Option<PowerMode> {
+    match value {
+        "1" => Some(PowerMode::Off),
+        "2" => Some(PowerMode::On),
+        "3" => Some(PowerMode::Standby),
+        "4" => Some(PowerMode::Suspend),
+        "5" => Some(PowerMode::HardOff),
+        _ => None,
+    }
+}
+
+fn parse_message(message: &str) -> Option<(&str, Vec<&str>)> {
+    message
+        .split_once(':')
+        .map(|(cmd, args)| (cmd, args.split(',').collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::monitors::{self, SimBackend};
+
+    fn server() -> Server {
+        let path = monitors::write_fixture(
+            "legacy",
+            r#"
+            [[monitors]]
+            id = 1
+            name = "Dell"
+            vcp = { "0x10" = 50 }
+            "#,
+        );
+        let backend = SimBackend::from_file(path).unwrap();
+        Server::new(std::sync::Arc::new(backend), Config::default()).unwrap()
+    }
+
+    fn reply(server: &mut Server, message: &[u8]) -> String {
+        String::from_utf8(handle(server, message)).unwrap()
+    }
+
+    #[test]
+    fn parses_multibyte_commands() {
+        assert_eq!(parse_message("é:x"), Some(("é", vec!["x"])));
+        assert_eq!(
+            parse_message("brightness:é,40"),
+            Some(("brightness", vec!["é", "40"]))
+        );
+        assert_eq!(parse_message("ééé"), None);
+    }
+
+    #[test]
+    fn rejects_multibyte_input() {
+        let mut server = server();
+
+        assert!(reply(&mut server, "é:x".as_bytes()).starts_with("error;bad_request;"));
+        assert!(reply(&mut server, "日本語".as_bytes()).starts_with("error;bad_request;"));
+        assert!(reply(&mut server, "set:é,2".as_bytes()).starts_with("error;unknown_monitor;"));
+    }
+
+    #[test]
+    fn rejects_invalid_utf8() {
+        let mut server = server();
+
+        assert!(reply(&mut server, b"\xff\xfe:\x80").starts_with("error;bad_request;"));
+        assert!(reply(&mut server, b"\xc3").starts_with("error;bad_request;"));
+        assert!(reply(&mut server, b"set:1,\xff").starts_with("error;bad_request;"));
+    }
+
+    #[test]
+    fn handles_valid_commands() {
+        let mut server = server();
+
+        assert_eq!(reply(&mut server, b"brightness:1,40"), "ok");
+        assert_eq!(
+            server.monitors()[0]
+                .get_vcp(monitors::VCP_BRIGHTNESS)
+                .unwrap()
+                .current,
+            40
+        );
+        assert!(reply(&mut server, b"list:").starts_with("1;Dell;2;"));
+    }
+
+    #[test]
+    fn sets_soft_and_hard_off() {
+        let mut server = server();
+        let power = |server: &Server| server.monitors()[0].power_mode().unwrap();
+
+        assert_eq!(reply(&mut server, b"set:1,1"), "ok");
+        assert_eq!(power(&server), PowerMode::Off);
+        assert!(reply(&mut server, b"list:").starts_with("1;Dell;1;"));
+
+        assert_eq!(reply(&mut server, b"set:1,5"), "ok");
+        assert_eq!(power(&server), PowerMode::HardOff);
+        assert!(reply(&mut server, b"list:").starts_with("1;Dell;1;"));
+
+        assert_eq!(reply(&mut server, b"set:1,2"), "ok");
+        assert_eq!(power(&server), PowerMode::On);
+        assert!(reply(&mut server, b"set:1,6").starts_with("error;bad_request;"));
+    }
+
+    #[test]
+    fn rejects_overflowing_fades() {
+        let mut server = server();
+
+        assert!(
+            reply(&mut server, b"brightness:1,40,99999999999999999999999s")
+                .starts_with("error;bad_request;")
+        );
+    }
+}
@@ -0,0 +1,499 @@
+mod error;
+mod events;
+mod http;
+mod legacy;
+mod mqtt;
+mod protocol;
+
+use std::error::Error;
+use std::net::{SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use env_logger::Env;
+use serde::Deserialize;
+
+use crate::adjustment::Adjustment;
+use crate::calibration::Calibration;
+use crate::config::Config;
+use crate::db::Db;
+use crate::fade::{self, Fades};
+use crate::monitors::{DisplayBackend, InputSource, Monitor, MonitorError, PowerMode, VcpValue};
+use crate::scene::{self, StepResult};
+use crate::selector::{Aliases, Selector};
+
+use events::{Event, Events};
+
+pub use error::{ErrorCode, ServerError};
+pub use mqtt::{MqttOptions, DEFAULT_PORT as DEFAULT_MQTT_PORT};
+
+/// Large enough for any UDP datagram, so that messages are never truncated.
+const MAX_DATAGRAM: usize = 65536;
+
+/// The state shared by every client of the server.
+pub struct Server {
+    db: Db,
+    fades: Arc<Fades>,
+    events: Arc<Events>,
+    config: Config,
+    aliases: Aliases,
+}
+
+/// Where to listen for commands, and which listeners to run alongside the
+/// UDP socket.
+#[derive(Debug)]
+pub struct Options {
+    pub udp: SocketAddr,
+    pub http: Option<SocketAddr>,
+    pub events: Option<SocketAddr>,
+    /// How often to check the monitors for changes to push to subscribers.
+    pub poll_interval: Duration,
+    pub mqtt: Option<MqttOptions>,
+}
+
+/// What to do to a monitor's speakers.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MuteState {
+    On,
+    Off,
+    Toggle,
+}
+
+pub fn run(
+    backend: Arc<dyn DisplayBackend>,
+    config: Config,
+    options: Options,
+) -> Result<(), Box<dyn Error>> {
+    env_logger::init_from_env(Env::default().default_filter_or("info"));
+
+    // Listeners each run on their own thread, and take turns with the
+    // server so that only one of them talks to the monitors at a time.
+    let server = Arc::new(Mutex::new(Server::new(backend, config)?));
+
+    let events = server.lock().unwrap().events.clone();
+
+    if let Some(addr) = options.http {
+        http::spawn(addr, server.clone())?;
+    }
+
+    if let Some(addr) = options.events {
+        events::spawn(addr, events)?;
+    }
+
+    if let Some(mqtt) = options.mqtt {
+        mqtt::spawn(mqtt, server.clone());
+    }
+
+    events::spawn_poller(server.clone(), options.poll_interval);
+
+    let socket = UdpSocket::bind(options.udp)?;
+
+    log::info!("server running on {}...", socket.local_addr()?);
+
+    serve(&socket, &server)
+}
+
+/// Answers commands sent to the socket, forever.
+fn serve(socket: &UdpSocket, server: &Mutex<Server>) -> ! {
+    let mut buffer = vec![0; MAX_DATAGRAM];
+
+    loop {
+        let (received, from) = match socket.recv_from(&mut buffer) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        let message = &buffer[..received];
+        let mut server = server.lock().unwrap();
+
+        // Framed messages start with a byte that can't appear at the start
+        // of a legacy text command.
+        let response = if protocol::is_frame(message) {
+            protocol::handle(&mut server, message)
+        } else {
+            legacy::handle(&mut server, message)
+        };
+
+        socket.send_to(&response, from).ok();
+    }
+}
+
+impl Server {
+    pub fn new(backend: Arc<dyn DisplayBackend>, config: Config) -> Result<Server, Box<dyn Error>> {
+        Ok(Server {
+            aliases: config.aliases()?,
+            db: Db::new(backend)?,
+            fades: Arc::new(Fades::default()),
+            events: Arc::new(Events::default()),
+            config,
+        })
+    }
+
+    pub fn monitors(&self) -> &[Monitor] {
+        self.db.monitors()
+    }
+
+    /// Selects the monitors for a command, cancelling any fades running on
+    /// them so that the command isn't undone by the next step of the fade.
+    fn select(&self, selector: &Selector) -> Result<Vec<&Monitor>, ServerError> {
+        let selected = self.find(selector)?;
+        for monitor in &selected {
+            self.fades.cancel(monitor);
+        }
+        Ok(selected)
+    }
+
+    /// Selects monitors for reading from them, which leaves fades running.
+    fn find(&self, selector: &Selector) -> Result<Vec<&Monitor>, ServerError> {
+        selector
+            .select(self.db.monitors(), &self.aliases)
+            .map_err(|e| ServerError::new(ErrorCode::UnknownMonitor, e))
+    }
+
+    /// Finds a single monitor, for requests about a particular one.
+    pub fn monitor(&self, selector: &Selector) -> Result<&Monitor, ServerError> {
+        match self.find(selector)?.as_slice() {
+            [monitor] => Ok(monitor),
+            selected => Err(ServerError::bad_request(format!(
+                "{} matches {} monitors, expected one",
+                selector,
+                selected.len()
+            ))),
+        }
+    }
+
+    /// Sets the power mode of the monitors. With a fade, monitors are dimmed
+    /// in the background before being turned off, and this returns straight
+    /// away.
+    pub fn set_power_mode(
+        &self,
+        selector: &Selector,
+        mode: PowerMode,
+        fade: Option<Duration>,
+    ) -> Result<(), ServerError> {
+        for monitor in self.select(selector)? {
+            match fade {
+                Some(duration) if mode != PowerMode::On => {
+                    self.fades.start(monitor, move |monitor, cancel| {
+                        fade::fade_off(monitor, mode, duration, cancel)
+                    });
+                }
+                _ => monitor
+                    .set_power_mode(mode)
+                    .map_err(|e| ServerError::monitor(monitor, "set power mode", e))?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adjusts a continuous VCP feature such as brightness, mapping
+    /// brightness through calibration curves the same way as the CLI.
+    pub fn set_level(
+        &self,
+        selector: &Selector,
+        code: u8,
+        adjustment: Adjustment,
+        fade: Option<Duration>,
+    ) -> Result<(), ServerError> {
+        let calibration = self.calibration()?;
+
+        for monitor in self.select(selector)? {
+            let action = format!("get vcp 0x{:02x}", code);
+            let value = monitor
+                .get_vcp(code)
+                .map_err(|e| ServerError::monitor(monitor, &action, e))?;
+            let new = calibration.adjust(monitor, code, adjustment, &value);
+
+            match fade {
+                Some(duration) => self.fades.start(monitor, move |monitor, cancel| {
+                    fade::fade(monitor, code, new, duration, cancel)
+                }),
+                None => {
+                    let action = format!("set vcp 0x{:02x}", code);
+                    monitor
+                        .set_vcp(code, new)
+                        .map_err(|e| ServerError::monitor(monitor, &action, e))?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get_vcp(&self, selector: &Selector, code: u8) -> Result<VcpValue, ServerError> {
+        let monitor = self.monitor(selector)?;
+        monitor
+            .get_vcp(code)
+            .map_err(|e| ServerError::monitor(monitor, &format!("get vcp 0x{:02x}", code), e))
+    }
+
+    pub fn set_vcp(&self, selector: &Selector, code: u8, value: u32) -> Result<(), ServerError> {
+        for monitor in self.select(selector)? {
+            monitor.set_vcp(code, value).map_err(|e| {
+                ServerError::monitor(monitor, &format!("set vcp 0x{:02x}", code), e)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_mute(&self, selector: &Selector, state: MuteState) -> Result<(), ServerError> {
+        for monitor in self.select(selector)? {
+            let result = match state {
+                MuteState::On => monitor.set_muted(true),
+                MuteState::Off => monitor.set_muted(false),
+                MuteState::Toggle => monitor.muted().and_then(|muted| monitor.set_muted(!muted)),
+            };
+
+            result.map_err(|e| ServerError::monitor(monitor, "set mute", e))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_input_source(
+        &self,
+        selector: &Selector,
+        source: InputSource,
+    ) -> Result<(), ServerError> {
+        for monitor in self.select(selector)? {
+            monitor
+                .set_input_source(source)
+                .map_err(|e| ServerError::monitor(monitor, "set input source", e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies a scene, returning the result of each action. Failing
+    /// actions don't fail the request, so that the rest are still reported.
+    pub fn apply_scene(&self, name: &str) -> Result<Vec<StepResult<'_>>, ServerError> {
+        let scene = self.config.scene(name).map_err(ServerError::bad_request)?;
+        for step in scene {
+            self.select(&step.monitors)?;
+        }
+
+        let calibration = self.calibration()?;
+        scene::apply(scene, self.db.monitors(), &self.aliases, &calibration)
+            .map_err(ServerError::bad_request)
+    }
+
+    /// Looks for monitors that have been connected or disconnected, and lets
+    /// subscribers know once it's done.
+    pub fn refresh(&mut self) -> Result<(), ServerError> {
+        self.enumerate()?;
+        self.events.update(self.db.monitors());
+        self.events.publish(Event::RefreshCompleted);
+        Ok(())
+    }
+
+    /// Publishes any changes to the monitors since they were last polled.
+    pub fn poll_events(&self) {
+        self.events.update(self.db.monitors());
+    }
+
+    /// Looks for monitors that have been connected or disconnected and
+    /// publishes the changes, but only while anyone is subscribed, since
+    /// enumerating them is slow.
+    pub fn rescan(&mut self) {
+        if !self.events.has_subscribers() {
+            return;
+        }
+
+        if let Err(e) = self.enumerate() {
+            log::warn!("failed to look for monitors: {}", e);
+        }
+        self.events.update(self.db.monitors());
+    }
+
+    /// Enumerates the monitors again. Fades carry on with the monitor as it
+    /// was when they started, so those on monitors that have gone, or that
+    /// now have another handle, are cancelled rather than left to write to
+    /// whatever the old handle points at.
+    fn enumerate(&mut self) -> Result<(), MonitorError> {
+        let old = self.db.monitors().to_vec();
+        self.db.refresh()?;
+
+        for monitor in &old {
+            let current = self
+                .db
+                .monitors()
+                .iter()
+                .any(|new| new.id() == monitor.id() && new.handle() == monitor.handle());
+            if !current {
+                self.fades.cancel(monitor);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn calibration(&self) -> Result<Calibration, ServerError> {
+        self.config
+            .calibration(self.db.monitors(), &self.aliases)
+            .map_err(|e| ServerError::bad_request(format!("invalid calibration: {}", e)))
+    }
+}
+
+impl FromStr for MuteState {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "on" => Ok(MuteState::On),
+            "off" => Ok(MuteState::Off),
+            "toggle" => Ok(MuteState::Toggle),
+            _ => Err(format!("invalid mute state: {}", s).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::monitors::{self, SimBackend};
+
+    /// Starts a server on a free port, returning its address.
+    fn start() -> SocketAddr {
+        let path = monitors::write_fixture(
+            "server",
+            r#"
+            [[monitors]]
+            id = 1
+            name = "Dell"
+            vcp = { "0x10" = 50 }
+
+            [[monitors]]
+            id = 2
+            name = "LG"
+            "#,
+        );
+        let backend = Arc::new(SimBackend::from_file(path).unwrap());
+        let server = Mutex::new(Server::new(backend, Config::default()).unwrap());
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || serve(&socket, &server));
+        addr
+    }
+
+    fn send(server: SocketAddr, message: &[u8]) -> Vec<u8> {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket.send_to(message, server).unwrap();
+
+        let mut buffer = vec![0; MAX_DATAGRAM];
+        let received = socket.recv(&mut buffer).unwrap();
+        buffer.truncate(received);
+        buffer
+    }
+
+    fn send_v2(server: SocketAddr, encoding: protocol::Encoding, request: Value) -> Value {
+        let body = encoding.encode(&request).unwrap();
+        let reply = send(server, &protocol::encode_frame(encoding, &body));
+
+        let (reply_encoding, body) = protocol::decode_frame(&reply).unwrap();
+        assert_eq!(reply_encoding, encoding);
+        encoding.decode(body).unwrap()
+    }
+
+    #[test]
+    fn legacy_round_trip() {
+        let server = start();
+
+        let list = String::from_utf8(send(server, b"list:")).unwrap();
+        let lines: Vec<_> = list.lines().collect();
+        assert_eq!(lines, ["1;Dell;2;;;;;sim:1", "2;LG;2;;;;;sim:2"]);
+
+        assert_eq!(send(server, b"set:2,1"), b"ok");
+        assert!(String::from_utf8(send(server, b"list:"))
+            .unwrap()
+            .contains("2;LG;1;"));
+
+        assert_eq!(send(server, b"brightness:1,+10"), b"ok");
+        assert_eq!(
+            send(server, b"set:9,2"),
+            b"error;unknown_monitor;no monitor found matching 9".to_vec()
+        );
+    }
+
+    #[test]
+    fn v2_round_trip() {
+        let server = start();
+
+        for encoding in [protocol::Encoding::Json, protocol::Encoding::Cbor] {
+            let reply = send_v2(
+                server,
+                encoding,
+                json!({ "version": 2, "id": 7, "command": "brightness", "monitors": "1", "value": 30 }),
+            );
+            assert_eq!(reply, json!({ "version": 2, "id": 7, "type": "ok" }));
+
+            let reply = send_v2(
+                server,
+                encoding,
+                json!({ "version": 2, "id": 8, "command": "list" }),
+            );
+            assert_eq!(reply["id"], 8);
+            assert_eq!(reply["type"], "monitors");
+            assert_eq!(reply["monitors"][0]["id"], "sim:1");
+            assert_eq!(reply["monitors"][0]["brightness"], 30);
+            assert_eq!(reply["monitors"][1]["power"], "on");
+        }
+
+        let reply = send_v2(
+            server,
+            protocol::Encoding::Json,
+            json!({ "version": 2, "id": 9, "command": "power", "monitors": "9", "mode": "off" }),
+        );
+        assert_eq!(reply["id"], 9);
+        assert_eq!(reply["type"], "error");
+        assert_eq!(reply["code"], "unknown_monitor");
+
+        let reply = send_v2(
+            server,
+            protocol::Encoding::Json,
+            json!({ "version": 1, "id": 10, "command": "list" }),
+        );
+        assert_eq!(reply["code"], "bad_request");
+    }
+
+    #[test]
+    fn rescans_only_while_subscribed() {
+        let dell = "[[monitors]]\nid = 1\nname = \"Dell\"\n";
+        let path = monitors::write_fixture("rescan", dell);
+        let backend = Arc::new(SimBackend::from_file(&path).unwrap());
+        let mut server = Server::new(backend, Config::default()).unwrap();
+
+        std::fs::write(
+            &path,
+            format!("{}[[monitors]]\nid = 2\nname = \"LG\"\n", dell),
+        )
+        .unwrap();
+        server.rescan();
+        assert_eq!(server.monitors().len(), 1);
+
+        let subscription = server.events.subscribe();
+        server.poll_events();
+        server.rescan();
+        assert_eq!(server.monitors().len(), 2);
+
+        let events: Vec<_> = subscription.try_iter().collect();
+        match events.as_slice() {
+            [event] => match &**event {
+                Event::Added { monitor, .. } => assert_eq!(monitor, "sim:2"),
+                event => panic!("expected the LG to be added, got {:?}", event),
+            },
+            events => panic!("expected one event, got {:?}", events),
+        }
+    }
+}
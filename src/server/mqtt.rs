@@ -0,0 +1,746 @@
+//! Bridges the server to an MQTT broker, for home automation systems such as
+//! Home Assistant. Each monitor gets a set of retained state topics under
+//! the prefix, e.g. for a prefix of `mona`:
+//!
+//! - `mona/<monitor>/power`: `ON` or `OFF`, where setting `OFF` puts the
+//!   monitor into soft off so that `ON` can wake it again
+//! - `mona/<monitor>/brightness`: the raw brightness value, which Home
+//!   Assistant scales by the monitor's maximum
+//! - `mona/<monitor>/input`: the input source
+//!
+//! Publishing to the same topics with `/set` appended changes the setting.
+//! `mona/status` is `online` while the bridge is connected, and is set to
+//! `offline` by the broker if the connection is lost.
+//!
+//! State is kept up to date by subscribing to the server's events, so the
+//! bridge doesn't talk to the monitors any more than the poller already does.
+
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rumqttc::{Client, Connection, LastWill, Packet, QoS};
+use serde::Serialize;
+
+use super::events::Event;
+use super::protocol::MonitorState;
+use super::{ErrorCode, Server, ServerError};
+use crate::monitors::{PowerMode, VCP_BRIGHTNESS};
+use crate::selector::{Matcher, Selector};
+
+pub const DEFAULT_PORT: u16 = 1883;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// Enough for the discovery and state messages of a handful of monitors to be
+// queued at once, as they are on connecting.
+const QUEUE_CAPACITY: usize = 256;
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+#[derive(Debug)]
+pub struct MqttOptions {
+    pub host: String,
+    pub port: u16,
+    /// The topic that monitors' topics go under. Also used as the client id,
+    /// since two bridges with the same prefix would fight over the topics.
+    pub prefix: String,
+    /// Where Home Assistant looks for discovery messages.
+    pub discovery_prefix: String,
+    pub credentials: Option<(String, String)>,
+}
+
+/// What the connection hands over to the bridge.
+enum Incoming {
+    Connected,
+    Command { topic: String, payload: Vec<u8> },
+}
+
+/// Publishes state and handles commands for the monitors. The MQTT side
+/// only goes through `client`, so a bridge made with `Client::from_sender`
+/// can be driven without a broker.
+pub struct Bridge {
+    client: Client,
+    server: Arc<Mutex<Server>>,
+    prefix: String,
+    discovery_prefix: String,
+}
+
+/// A Home Assistant MQTT discovery payload. Only the fields for the kind of
+/// entity being configured are set.
+#[derive(Serialize)]
+struct Discovery<'a> {
+    name: String,
+    unique_id: String,
+    availability_topic: String,
+    device: Device<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command_topic: Option<String>,
+    state_topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_on: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_off: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brightness_command_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brightness_state_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brightness_scale: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct Device<'a> {
+    identifiers: Vec<String>,
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manufacturer: Option<&'a str>,
+    model: &'a str,
+}
+
+/// Connects to the broker on new threads, reconnecting whenever the
+/// connection is lost.
+pub fn spawn(options: MqttOptions, server: Arc<Mutex<Server>>) {
+    let mut client_options =
+        rumqttc::MqttOptions::new(options.prefix.clone(), options.host.clone(), options.port);
+    client_options
+        .set_keep_alive(KEEP_ALIVE)
+        .set_last_will(LastWill::new(
+            status_topic(&options.prefix),
+            OFFLINE,
+            QoS::AtLeastOnce,
+            true,
+        ));
+    if let Some((username, password)) = options.credentials {
+        client_options.set_credentials(username, password);
+    }
+
+    let (client, connection) = Client::new(client_options, QUEUE_CAPACITY);
+    let subscription = server.lock().unwrap().events.subscribe();
+
+    let bridge = Arc::new(Bridge::new(
+        client,
+        server,
+        options.prefix,
+        options.discovery_prefix,
+    ));
+
+    log::info!(
+        "mqtt bridge connecting to {}:{}...",
+        options.host,
+        options.port
+    );
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || run_connection(connection, sender));
+
+    // Commands are handled away from the connection, which has to keep being
+    // polled while the monitors are slow to respond.
+    let commands = bridge.clone();
+    thread::spawn(move || {
+        for incoming in receiver {
+            match incoming {
+                Incoming::Connected => commands.connected(),
+                Incoming::Command { topic, payload } => commands.command(&topic, &payload),
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        for event in subscription.iter() {
+            bridge.event(&event);
+        }
+    });
+}
+
+fn run_connection(mut connection: Connection, sender: Sender<Incoming>) {
+    for notification in connection.iter() {
+        let incoming = match notification {
+            Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                log::info!("mqtt bridge connected");
+                Incoming::Connected
+            }
+            Ok(rumqttc::Event::Incoming(Packet::Publish(publish))) => Incoming::Command {
+                topic: publish.topic,
+                payload: publish.payload.to_vec(),
+            },
+            Ok(_) => continue,
+            Err(e) => {
+                // Polling the connection again reconnects.
+                log::warn!(
+                    "mqtt connection failed, retrying in {:?}: {}",
+                    RECONNECT_DELAY,
+                    e
+                );
+                thread::sleep(RECONNECT_DELAY);
+                continue;
+            }
+        };
+
+        if sender.send(incoming).is_err() {
+            break;
+        }
+    }
+}
+
+impl Bridge {
+    pub fn new(
+        client: Client,
+        server: Arc<Mutex<Server>>,
+        prefix: String,
+        discovery_prefix: String,
+    ) -> Bridge {
+        Bridge {
+            client,
+            server,
+            prefix,
+            discovery_prefix,
+        }
+    }
+
+    /// Subscribes to commands and publishes everything from scratch, since
+    /// the broker may have lost it, or missed changes while disconnected.
+    pub fn connected(&self) {
+        if let Err(e) = self
+            .client
+            .try_subscribe(format!("{}/+/+/set", self.prefix), QoS::AtLeastOnce)
+        {
+            log::error!("failed to subscribe to mqtt commands: {}", e);
+        }
+
+        self.publish(&status_topic(&self.prefix), ONLINE);
+
+        let states: Vec<_> = {
+            let server = self.server.lock().unwrap();
+            server.monitors().iter().map(MonitorState::new).collect()
+        };
+
+        for state in &states {
+            self.publish_discovery(state);
+            self.publish_state(state);
+        }
+    }
+
+    /// Handles a message on a `…/set` topic.
+    pub fn command(&self, topic: &str, payload: &[u8]) {
+        let payload = String::from_utf8_lossy(payload);
+        log::debug!("mqtt command: {} {}", topic, payload);
+
+        let segments: Vec<&str> = topic
+            .strip_prefix(&self.prefix)
+            .and_then(|topic| topic.strip_prefix('/'))
+            .map(|topic| topic.split('/').collect())
+            .unwrap_or_default();
+
+        let (monitor, setting) = match segments.as_slice() {
+            [monitor, setting, "set"] => (*monitor, *setting),
+            _ => {
+                log::warn!("ignoring message on unexpected mqtt topic: {}", topic);
+                return;
+            }
+        };
+
+        let server = self.server.lock().unwrap();
+
+        let result = self
+            .selector(&server, monitor)
+            .and_then(|selector| match setting {
+                "power" => {
+                    let mode = match payload.trim().to_ascii_uppercase().as_str() {
+                        "ON" => PowerMode::On,
+                        "OFF" => PowerMode::Off,
+                        _ => {
+                            return Err(ServerError::bad_request(format!(
+                                "invalid power state: {}",
+                                payload
+                            )))
+                        }
+                    };
+                    server.set_power_mode(&selector, mode, None)
+                }
+                // The same raw value as the state topic, so calibration curves
+                // don't apply: Home Assistant already scales it by the max.
+                "brightness" => {
+                    let value = payload.trim().parse().map_err(|_| {
+                        ServerError::bad_request(format!("invalid brightness: {}", payload))
+                    })?;
+                    server.set_vcp(&selector, VCP_BRIGHTNESS, value)
+                }
+                "input" => {
+                    let source = payload.trim().parse().map_err(ServerError::bad_request)?;
+                    server.set_input_source(&selector, source)
+                }
+                _ => Err(ServerError::bad_request(format!(
+                    "unknown setting: {}",
+                    setting
+                ))),
+            });
+
+        match result {
+            // Publishes the new state straight away, rather than waiting for
+            // the next poll.
+            Ok(()) => server.poll_events(),
+            Err(e) => log::error!("mqtt command on {} failed: {}", topic, e),
+        }
+    }
+
+    /// Publishes the changes described by an event.
+    pub fn event(&self, event: &Event) {
+        match event {
+            Event::Power { monitor, power } => {
+                if let Some(power) = power {
+                    self.publish(&self.topic(monitor, "power"), power_payload(power));
+                }
+            }
+            Event::Brightness {
+                monitor,
+                brightness,
+            } => {
+                if let Some(brightness) = brightness {
+                    self.publish(&self.topic(monitor, "brightness"), brightness.to_string());
+                }
+            }
+            Event::Input { monitor, input } => {
+                if let Some(input) = input {
+                    self.publish(&self.topic(monitor, "input"), input.as_str());
+                }
+            }
+            Event::Added { state, .. } => {
+                self.publish_discovery(state);
+                self.publish_state(state);
+            }
+            Event::Removed { monitor } => {
+                // Empty retained messages delete what was there, which also
+                // removes the entities from Home Assistant.
+                for component in &["light", "switch", "sensor"] {
+                    self.publish(&self.discovery_topic(component, monitor), "");
+                }
+                for setting in &["power", "brightness", "input"] {
+                    self.publish(&self.topic(monitor, setting), "");
+                }
+            }
+            Event::RefreshCompleted => {}
+        }
+    }
+
+    fn publish_state(&self, state: &MonitorState) {
+        if let Some(power) = &state.power {
+            self.publish(&self.topic(&state.id, "power"), power_payload(power));
+        }
+        if let Some(brightness) = state.brightness {
+            self.publish(&self.topic(&state.id, "brightness"), brightness.to_string());
+        }
+        if let Some(input) = &state.input {
+            self.publish(&self.topic(&state.id, "input"), input.as_str());
+        }
+    }
+
+    /// Monitors with a brightness are set up as dimmable lights, and the rest
+    /// as switches. The input source, if it can be read, is a sensor.
+    fn publish_discovery(&self, state: &MonitorState) {
+        let object_id = format!("mona_{}", topic_id(&state.id));
+        let device = || Device {
+            identifiers: vec![object_id.clone()],
+            name: &state.name,
+            manufacturer: state.manufacturer.as_deref(),
+            model: &state.name,
+        };
+
+        let power = Discovery {
+            name: state.name.clone(),
+            unique_id: object_id.clone(),
+            availability_topic: status_topic(&self.prefix),
+            device: device(),
+            command_topic: Some(self.topic(&state.id, "power/set")),
+            state_topic: self.topic(&state.id, "power"),
+            payload_on: Some("ON"),
+            payload_off: Some("OFF"),
+            brightness_command_topic: None,
+            brightness_state_topic: None,
+            brightness_scale: None,
+        };
+
+        let (component, power, stale) = match state.max_brightness {
+            Some(max) => (
+                "light",
+                Discovery {
+                    brightness_command_topic: Some(self.topic(&state.id, "brightness/set")),
+                    brightness_state_topic: Some(self.topic(&state.id, "brightness")),
+                    brightness_scale: Some(max),
+                    ..power
+                },
+                "switch",
+            ),
+            None => ("switch", power, "light"),
+        };
+
+        self.publish(
+            &self.discovery_topic(component, &state.id),
+            serde_json::to_vec(&power).unwrap(),
+        );
+        // Clears the other kind, in case the monitor has changed since.
+        self.publish(&self.discovery_topic(stale, &state.id), "");
+
+        if state.input.is_some() {
+            let input = Discovery {
+                name: format!("{} input", state.name),
+                unique_id: format!("{}_input", object_id),
+                availability_topic: status_topic(&self.prefix),
+                device: device(),
+                command_topic: None,
+                state_topic: self.topic(&state.id, "input"),
+                payload_on: None,
+                payload_off: None,
+                brightness_command_topic: None,
+                brightness_state_topic: None,
+                brightness_scale: None,
+            };
+
+            self.publish(
+                &self.discovery_topic("sensor", &state.id),
+                serde_json::to_vec(&input).unwrap(),
+            );
+        }
+    }
+
+    /// State and discovery messages are all retained, so that subscribers
+    /// get the latest as soon as they subscribe.
+    fn publish(&self, topic: &str, payload: impl Into<Vec<u8>>) {
+        // Publishing only queues the message for the connection, which fails
+        // if the queue is full. Everything is published again on reconnecting,
+        // so nothing is lost for good.
+        if let Err(e) = self
+            .client
+            .try_publish(topic, QoS::AtLeastOnce, true, payload)
+        {
+            log::warn!("failed to publish to {}: {}", topic, e);
+        }
+    }
+
+    fn topic(&self, monitor: &str, setting: &str) -> String {
+        format!("{}/{}/{}", self.prefix, topic_id(monitor), setting)
+    }
+
+    fn discovery_topic(&self, component: &str, monitor: &str) -> String {
+        format!(
+            "{}/{}/mona_{}/config",
+            self.discovery_prefix,
+            component,
+            topic_id(monitor)
+        )
+    }
+
+    /// Finds the monitor that a topic refers to.
+    fn selector(&self, server: &Server, monitor: &str) -> Result<Selector, ServerError> {
+        server
+            .monitors()
+            .iter()
+            .find(|m| topic_id(m.id()) == monitor)
+            .map(|m| {
+                Selector::matching(Matcher {
+                    id: Some(m.id().to_owned()),
+                    ..Matcher::default()
+                })
+            })
+            .ok_or_else(|| {
+                ServerError::new(
+                    ErrorCode::UnknownMonitor,
+                    format!("no monitor found for topic {}", monitor),
+                )
+            })
+    }
+}
+
+fn status_topic(prefix: &str) -> String {
+    format!("{}/status", prefix)
+}
+
+fn power_payload(power: &str) -> &'static str {
+    if power == PowerMode::On.to_string() {
+        "ON"
+    } else {
+        "OFF"
+    }
+}
+
+/// Monitor ids can contain characters that mean something in topics, such
+/// as `/`, `+` and `#`, so everything other than letters, digits, `-` and `_`
+/// is replaced.
+fn topic_id(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rumqttc::Request;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::monitors::{self, SimBackend};
+
+    const DELL: &str = "del-a0b8-abc123";
+
+    /// A bridge over a Dell with a brightness out of 80 and an input source,
+    /// and an LG with neither, along with what the bridge sends out.
+    fn bridge() -> (Bridge, flume::Receiver<Request>) {
+        let path = monitors::write_fixture(
+            "mqtt",
+            r#"
+            [[monitors]]
+            id = 1
+            name = "Dell"
+            vcp = { "0x10" = { current = 60, max = 80 }, "0x60" = 0x11 }
+            edid = { manufacturer = "DEL", product = 0xa0b8, serial = "ABC123", year = 2020 }
+
+            [[monitors]]
+            id = 2
+            name = "LG"
+            "#,
+        );
+        let backend = Arc::new(SimBackend::from_file(path).unwrap());
+        let server = Server::new(backend, Config::default()).unwrap();
+
+        let (sender, receiver) = flume::unbounded();
+        let bridge = Bridge::new(
+            Client::from_sender(sender),
+            Arc::new(Mutex::new(server)),
+            "mona".to_owned(),
+            "homeassistant".to_owned(),
+        );
+        (bridge, receiver)
+    }
+
+    /// The messages published so far, in order.
+    fn published(requests: &flume::Receiver<Request>) -> Vec<(String, String)> {
+        requests
+            .try_iter()
+            .filter_map(|request| match request {
+                Request::Publish(publish) => Some((
+                    publish.topic,
+                    String::from_utf8(publish.payload.to_vec()).unwrap(),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn brightness(bridge: &Bridge) -> u32 {
+        let server = bridge.server.lock().unwrap();
+        server.monitors()[0]
+            .get_vcp(VCP_BRIGHTNESS)
+            .unwrap()
+            .current
+    }
+
+    fn power_mode(bridge: &Bridge) -> PowerMode {
+        let server = bridge.server.lock().unwrap();
+        server.monitors()[0].power_mode().unwrap()
+    }
+
+    #[test]
+    fn publishes_everything_on_connecting() {
+        let (bridge, requests) = bridge();
+        bridge.connected();
+
+        match requests.try_recv().unwrap() {
+            Request::Subscribe(subscribe) => {
+                assert_eq!(subscribe.filters[0].path, "mona/+/+/set");
+            }
+            request => panic!("expected a subscription, got {:?}", request),
+        }
+
+        let published: HashMap<_, _> = published(&requests).into_iter().collect();
+        let discovery = |topic: &str| -> Value { serde_json::from_str(&published[topic]).unwrap() };
+
+        assert_eq!(published["mona/status"], "online");
+
+        let light = discovery("homeassistant/light/mona_del-a0b8-abc123/config");
+        assert_eq!(light["unique_id"], "mona_del-a0b8-abc123");
+        assert_eq!(light["command_topic"], "mona/del-a0b8-abc123/power/set");
+        assert_eq!(light["state_topic"], "mona/del-a0b8-abc123/power");
+        assert_eq!(
+            light["brightness_command_topic"],
+            "mona/del-a0b8-abc123/brightness/set"
+        );
+        assert_eq!(
+            light["brightness_state_topic"],
+            "mona/del-a0b8-abc123/brightness"
+        );
+        assert_eq!(light["brightness_scale"], 80);
+        assert_eq!(light["device"]["manufacturer"], "DEL");
+        assert_eq!(
+            published["homeassistant/switch/mona_del-a0b8-abc123/config"],
+            ""
+        );
+
+        let input = discovery("homeassistant/sensor/mona_del-a0b8-abc123/config");
+        assert_eq!(input["state_topic"], "mona/del-a0b8-abc123/input");
+        assert!(input.get("command_topic").is_none());
+
+        assert_eq!(published["mona/del-a0b8-abc123/power"], "ON");
+        assert_eq!(published["mona/del-a0b8-abc123/brightness"], "60");
+        assert_eq!(published["mona/del-a0b8-abc123/input"], "hdmi1");
+
+        // The LG's brightness can't be read, so it's only a switch.
+        let switch = discovery("homeassistant/switch/mona_sim_2/config");
+        assert_eq!(switch["command_topic"], "mona/sim_2/power/set");
+        assert!(switch.get("brightness_scale").is_none());
+        assert_eq!(published["homeassistant/light/mona_sim_2/config"], "");
+        assert!(!published.contains_key("homeassistant/sensor/mona_sim_2/config"));
+        assert_eq!(published["mona/sim_2/power"], "ON");
+        assert!(!published.contains_key("mona/sim_2/brightness"));
+    }
+
+    #[test]
+    fn handles_commands() {
+        let (bridge, _requests) = bridge();
+
+        bridge.command(&format!("mona/{}/brightness/set", DELL), b"70");
+        assert_eq!(brightness(&bridge), 70);
+
+        // Brightness is the raw value, up to the monitor's max.
+        bridge.command(&format!("mona/{}/brightness/set", DELL), b" 80\n");
+        assert_eq!(brightness(&bridge), 80);
+        bridge.command(&format!("mona/{}/brightness/set", DELL), b"90");
+        bridge.command(&format!("mona/{}/brightness/set", DELL), b"50%");
+        assert_eq!(brightness(&bridge), 80);
+
+        bridge.command(&format!("mona/{}/power/set", DELL), b"off");
+        assert_eq!(power_mode(&bridge), PowerMode::Off);
+        bridge.command(&format!("mona/{}/power/set", DELL), b"ON");
+        assert_eq!(power_mode(&bridge), PowerMode::On);
+        bridge.command(&format!("mona/{}/power/set", DELL), b"standby");
+        assert_eq!(power_mode(&bridge), PowerMode::On);
+
+        bridge.command(&format!("mona/{}/input/set", DELL), b"dp1");
+        let server = bridge.server.lock().unwrap();
+        assert_eq!(
+            server.monitors()[0].input_source().unwrap().to_string(),
+            "dp1"
+        );
+    }
+
+    #[test]
+    fn ignores_unexpected_topics() {
+        let (bridge, requests) = bridge();
+
+        for topic in &[
+            "mona/del-a0b8-abc123/brightness",
+            "mona/del-a0b8-abc123/brightness/get",
+            "mona/del-a0b8-abc123/brightness/set/more",
+            "mona/DEL-A0B8-ABC123/brightness/set",
+            "mona/del-a0b8-abc123/contrast/set",
+            "mona/unknown/brightness/set",
+            "other/del-a0b8-abc123/brightness/set",
+            "monarch/del-a0b8-abc123/brightness/set",
+            "mona",
+            "",
+        ] {
+            bridge.command(topic, b"10");
+        }
+
+        assert_eq!(brightness(&bridge), 60);
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn publishes_events() {
+        let (bridge, requests) = bridge();
+
+        bridge.event(&Event::Brightness {
+            monitor: "DEL-A0B8-ABC123".to_owned(),
+            brightness: Some(40),
+        });
+        bridge.event(&Event::Power {
+            monitor: "DEL-A0B8-ABC123".to_owned(),
+            power: Some(PowerMode::Standby.to_string()),
+        });
+        bridge.event(&Event::Input {
+            monitor: "DEL-A0B8-ABC123".to_owned(),
+            input: Some("dp1".to_owned()),
+        });
+        // Settings that can no longer be read leave the last state alone.
+        bridge.event(&Event::Brightness {
+            monitor: "DEL-A0B8-ABC123".to_owned(),
+            brightness: None,
+        });
+        bridge.event(&Event::RefreshCompleted);
+
+        assert_eq!(
+            published(&requests),
+            vec![
+                (
+                    "mona/del-a0b8-abc123/brightness".to_owned(),
+                    "40".to_owned()
+                ),
+                ("mona/del-a0b8-abc123/power".to_owned(), "OFF".to_owned()),
+                ("mona/del-a0b8-abc123/input".to_owned(), "dp1".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn publishes_added_monitors() {
+        let (bridge, requests) = bridge();
+
+        let state = {
+            let server = bridge.server.lock().unwrap();
+            MonitorState::new(&server.monitors()[0])
+        };
+        bridge.event(&Event::Added {
+            monitor: state.id.clone(),
+            state,
+        });
+
+        let published: HashMap<_, _> = published(&requests).into_iter().collect();
+        assert!(published.contains_key("homeassistant/light/mona_del-a0b8-abc123/config"));
+        assert!(published.contains_key("homeassistant/sensor/mona_del-a0b8-abc123/config"));
+        assert_eq!(published["mona/del-a0b8-abc123/brightness"], "60");
+    }
+
+    #[test]
+    fn clears_removed_monitors() {
+        let (bridge, requests) = bridge();
+
+        bridge.event(&Event::Removed {
+            monitor: "sim:2".to_owned(),
+        });
+
+        // Empty payloads are retained too, so that the broker forgets them.
+        let mut cleared: Vec<_> = requests
+            .try_iter()
+            .map(|request| match request {
+                Request::Publish(publish) if publish.retain && publish.payload.is_empty() => {
+                    publish.topic
+                }
+                request => panic!("expected a retained empty publish, got {:?}", request),
+            })
+            .collect();
+        cleared.sort();
+
+        assert_eq!(
+            cleared,
+            [
+                "homeassistant/light/mona_sim_2/config",
+                "homeassistant/sensor/mona_sim_2/config",
+                "homeassistant/switch/mona_sim_2/config",
+                "mona/sim_2/brightness",
+                "mona/sim_2/input",
+                "mona/sim_2/power",
+            ]
+        );
+    }
+}
@@ -0,0 +1,417 @@
+//! Version 2 of the server protocol. Each message is a frame holding a JSON
+//! or CBOR body:
+//!
+//! ```text
+//! +------+----------+-----------------+-------------+
+//! | 0x00 | encoding | length (u32 BE) | body        |
+//! +------+----------+-----------------+-------------+
+//! ```
+//!
+//! where the encoding is `j` for JSON or `c` for CBOR. The length covers just
+//! the body, so a frame that was cut short is rejected rather than misread.
+//! Replies are framed and encoded the same way as the request.
+//!
+//! A request names its command alongside the protocol version and an id,
+//! which is echoed back in the reply:
+//!
+//! ```json
+//! { "version": 2, "id": 7, "command": "brightness", "monitors": "desk", "value": "40%" }
+//! ```
+//!
+//! ```json
+//! { "version": 2, "id": 7, "type": "ok" }
+//! ```
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use super::{ErrorCode, MuteState, Server, ServerError};
+use crate::adjustment::Adjustment;
+use crate::fade;
+use crate::monitors::{InputSource, Monitor, PowerMode, VCP_BRIGHTNESS, VCP_CONTRAST, VCP_VOLUME};
+use crate::selector::Selector;
+
+pub const VERSION: u32 = 2;
+
+const FRAME_MARKER: u8 = 0x00;
+const HEADER_LEN: usize = 6;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Cbor,
+}
+
+/// Just enough of a request to reply to it, read separately so that the id
+/// can be echoed back even if the rest of the request is invalid.
+#[derive(Deserialize)]
+struct Envelope {
+    version: Option<u32>,
+    #[serde(default)]
+    id: u64,
+}
+
+/// The rest of a request, read from the same body as its envelope.
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    List,
+    Refresh,
+    Power {
+        monitors: Selector,
+        mode: PowerMode,
+        #[serde(default, deserialize_with = "deserialize_fade")]
+        fade: Option<Duration>,
+    },
+    Brightness {
+        monitors: Selector,
+        value: Adjustment,
+        #[serde(default, deserialize_with = "deserialize_fade")]
+        fade: Option<Duration>,
+    },
+    Contrast {
+        monitors: Selector,
+        value: Adjustment,
+        #[serde(default, deserialize_with = "deserialize_fade")]
+        fade: Option<Duration>,
+    },
+    Volume {
+        monitors: Selector,
+        value: Adjustment,
+        #[serde(default, deserialize_with = "deserialize_fade")]
+        fade: Option<Duration>,
+    },
+    Mute {
+        monitors: Selector,
+        state: MuteState,
+    },
+    Input {
+        monitors: Selector,
+        source: InputSource,
+    },
+    Scene {
+        name: String,
+    },
+}
+
+#[derive(Serialize)]
+pub struct Response {
+    pub version: u32,
+    pub id: u64,
+    #[serde(flatten)]
+    pub body: ResponseBody,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseBody {
+    Ok,
+    Monitors { monitors: Vec<MonitorState> },
+    Scene { steps: Vec<SceneStep> },
+    Error { code: ErrorCode, message: String },
+}
+
+/// A monitor as reported to clients. Settings are `None` if they couldn't
+/// be read, usually because the monitor doesn't support them.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MonitorState {
+    pub id: String,
+    pub index: i32,
+    pub name: String,
+    pub power: Option<String>,
+    pub brightness: Option<u32>,
+    pub max_brightness: Option<u32>,
+    pub input: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<u16>,
+    pub serial: Option<String>,
+    pub year: Option<u16>,
+}
+
+#[derive(Serialize)]
+pub struct SceneStep {
+    pub monitor: String,
+    pub action: String,
+    /// `None` if the action failed.
+    pub result: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Whether a message is a frame rather than a legacy text command.
+pub fn is_frame(message: &[u8]) -> bool {
+    message.first() == Some(&FRAME_MARKER)
+}
+
+/// Handles a frame, returning the framed reply.
+pub fn handle(server: &mut Server, message: &[u8]) -> Vec<u8> {
+    let (encoding, body) = match decode_frame(message) {
+        Ok(frame) => frame,
+        Err(e) => {
+            // Without a valid frame there's no telling how the client wants
+            // the reply, so fall back to JSON.
+            log::error!("{}", e);
+            return reply(Encoding::Json, 0, error(ServerError::bad_request(e)));
+        }
+    };
+
+    let envelope: Envelope = match encoding.decode(body) {
+        Ok(envelope) => envelope,
+        Err(e) => {
+            log::error!("invalid request: {}", e);
+            return reply(encoding, 0, error(ServerError::bad_request(e)));
+        }
+    };
+
+    if envelope.version != Some(VERSION) {
+        let message = match envelope.version {
+            Some(version) => format!("unsupported protocol version: {}", version),
+            None => "missing protocol version".to_owned(),
+        };
+        log::error!("{}", message);
+        return reply(
+            encoding,
+            envelope.id,
+            error(ServerError::bad_request(message)),
+        );
+    }
+
+    let body = encoding
+        .decode::<Command>(body)
+        .map_err(ServerError::bad_request)
+        .and_then(|command| execute(server, command));
+
+    let body = body.unwrap_or_else(|e| {
+        log::error!("request {} failed: {}", envelope.id, e);
+        error(e)
+    });
+
+    reply(encoding, envelope.id, body)
+}
+
+fn execute(server: &mut Server, command: Command) -> Result<ResponseBody, ServerError> {
+    match command {
+        Command::List => Ok(ResponseBody::Monitors {
+            monitors: server.monitors().iter().map(MonitorState::new).collect(),
+        }),
+        Command::Refresh => {
+            server.refresh()?;
+            Ok(ResponseBody::Ok)
+        }
+        Command::Power {
+            monitors,
+            mode,
+            fade,
+        } => {
+            server.set_power_mode(&monitors, mode, fade)?;
+            Ok(ResponseBody::Ok)
+        }
+        Command::Brightness {
+            monitors,
+            value,
+            fade,
+        } => {
+            server.set_level(&monitors, VCP_BRIGHTNESS, value, fade)?;
+            Ok(ResponseBody::Ok)
+        }
+        Command::Contrast {
+            monitors,
+            value,
+            fade,
+        } => {
+            server.set_level(&monitors, VCP_CONTRAST, value, fade)?;
+            Ok(ResponseBody::Ok)
+        }
+        Command::Volume {
+            monitors,
+            value,
+            fade,
+        } => {
+            server.set_level(&monitors, VCP_VOLUME, value, fade)?;
+            Ok(ResponseBody::Ok)
+        }
+        Command::Mute { monitors, state } => {
+            server.set_mute(&monitors, state)?;
+            Ok(ResponseBody::Ok)
+        }
+        Command::Input { monitors, source } => {
+            server.set_input_source(&monitors, source)?;
+            Ok(ResponseBody::Ok)
+        }
+        Command::Scene { name } => {
+            let steps = server
+                .apply_scene(&name)?
+                .into_iter()
+                .map(|step| {
+                    let (result, error) = match step.result {
+                        Ok(result) => (Some(result), None),
+                        Err(e) => (None, Some(e.to_string())),
+                    };
+
+                    SceneStep {
+                        monitor: step.monitor.id().to_owned(),
+                        action: step.action.to_string(),
+                        result,
+                        error,
+                    }
+                })
+                .collect();
+
+            Ok(ResponseBody::Scene { steps })
+        }
+    }
+}
+
+impl MonitorState {
+    pub fn new(monitor: &Monitor) -> MonitorState {
+        let power = match monitor.power_mode() {
+            Ok(mode) => Some(mode.to_string()),
+            Err(e) => {
+                log::warn!(
+                    "failed to get power mode of monitor {}: {}",
+                    monitor.id(),
+                    e
+                );
+                None
+            }
+        };
+
+        let brightness = match monitor.get_vcp(VCP_BRIGHTNESS) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                log::debug!(
+                    "failed to get brightness of monitor {}: {}",
+                    monitor.id(),
+                    e
+                );
+                None
+            }
+        };
+
+        let input = match monitor.input_source() {
+            Ok(source) => Some(source.to_string()),
+            Err(e) => {
+                log::debug!(
+                    "failed to get input source of monitor {}: {}",
+                    monitor.id(),
+                    e
+                );
+                None
+            }
+        };
+
+        let edid = monitor.edid();
+
+        MonitorState {
+            id: monitor.id().to_owned(),
+            index: monitor.index(),
+            name: monitor.name().to_owned(),
+            power,
+            brightness: brightness.as_ref().map(|value| value.current),
+            max_brightness: brightness.map(|value| value.max),
+            input,
+            manufacturer: edid.map(|edid| edid.manufacturer.clone()),
+            product: edid.map(|edid| edid.product),
+            serial: edid.and_then(|edid| edid.serial()),
+            year: edid.map(|edid| edid.year),
+        }
+    }
+}
+
+impl Encoding {
+    fn tag(self) -> u8 {
+        match self {
+            Encoding::Json => b'j',
+            Encoding::Cbor => b'c',
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Encoding> {
+        match tag {
+            b'j' => Some(Encoding::Json),
+            b'c' => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            Encoding::Json => Ok(serde_json::to_vec(value)?),
+            Encoding::Cbor => Ok(serde_cbor::to_vec(value)?),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, body: &[u8]) -> Result<T, Box<dyn Error>> {
+        match self {
+            Encoding::Json => Ok(serde_json::from_slice(body)?),
+            Encoding::Cbor => Ok(serde_cbor::from_slice(body)?),
+        }
+    }
+}
+
+/// Splits a frame into its encoding and body, checking that the body is
+/// as long as the header says.
+pub fn decode_frame(frame: &[u8]) -> Result<(Encoding, &[u8]), Box<dyn Error>> {
+    if frame.len() < HEADER_LEN || frame[0] != FRAME_MARKER {
+        return Err("invalid frame header".into());
+    }
+
+    let encoding = Encoding::from_tag(frame[1])
+        .ok_or_else(|| format!("unknown encoding: 0x{:02x}", frame[1]))?;
+
+    let len = u32::from_be_bytes([frame[2], frame[3], frame[4], frame[5]]) as usize;
+    let body = &frame[HEADER_LEN..];
+    if body.len() != len {
+        return Err(format!(
+            "frame length mismatch: header says {} bytes, got {}",
+            len,
+            body.len()
+        )
+        .into());
+    }
+
+    Ok((encoding, body))
+}
+
+pub fn encode_frame(encoding: Encoding, body: &[u8]) -> Vec<u8> {
+    let len = u32::try_from(body.len()).expect("frame body too large");
+
+    let mut frame = Vec::with_capacity(HEADER_LEN + body.len());
+    frame.push(FRAME_MARKER);
+    frame.push(encoding.tag());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(body);
+    frame
+}
+
+fn reply(encoding: Encoding, id: u64, body: ResponseBody) -> Vec<u8> {
+    let response = Response {
+        version: VERSION,
+        id,
+        body,
+    };
+
+    // Responses are made up of plain strings and numbers, so can always be
+    // encoded.
+    let body = encoding.encode(&response).unwrap();
+    encode_frame(encoding, &body)
+}
+
+fn error(e: ServerError) -> ResponseBody {
+    ResponseBody::Error {
+        code: e.code,
+        message: e.message,
+    }
+}
+
+/// Fades are given the same way as on the command line, e.g. `500ms`.
+fn deserialize_fade<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    let fade = Option::<String>::deserialize(deserializer)?;
+    fade.map(|fade| fade::parse_duration(&fade).map_err(de::Error::custom))
+        .transpose()
+}
@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::monitors::{self, Monitor, MonitorError, VcpType};
+
+/// Features that are advertised in capabilities strings but shouldn't be
+/// restored, either because they can't be written, trigger an action when
+/// they are, or are better left as they are (power and input).
+const SKIPPED_FEATURES: &[u8] = &[
+    0x01, // degauss
+    0x02, // new control value
+    0x04, // restore factory defaults
+    0x05, // restore factory brightness/contrast
+    0x06, // restore factory geometry
+    0x08, // restore factory color
+    0x0a, // restore factory tv
+    0x52, // active control
+    0x60, // input source
+    0xac, // horizontal frequency
+    0xae, // vertical frequency
+    0xb2, // flat panel sub-pixel layout
+    0xb6, // display technology type
+    0xc0, // display usage time
+    0xc6, // application enable key
+    0xc8, // display controller type
+    0xc9, // display firmware level
+    0xd6, // power mode
+    0xdf, // vcp version
+];
+
+// Codes from here on are manufacturer specific, so there's no telling what
+// writing them back would do.
+const MANUFACTURER_FEATURES: u8 = 0xe0;
+
+/// The VCP values of a set of monitors, keyed by stable id.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub monitors: BTreeMap<String, MonitorSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MonitorSnapshot {
+    pub name: String,
+    /// Values keyed by hex VCP code, e.g. `0x10`.
+    pub vcp: BTreeMap<String, u32>,
+}
+
+/// A feature whose current value differs from the one in a snapshot.
+pub struct Difference {
+    pub code: u8,
+    pub saved: u32,
+    pub current: Result<u32, MonitorError>,
+}
+
+impl Snapshot {
+    pub fn load(name: &str) -> Result<Snapshot, Box<dyn Error>> {
+        let path = path(name)?;
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read snapshot {}: {}", path.display(), e))?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn save(&self, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let path = path(name)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
+
+impl MonitorSnapshot {
+    /// Reads every feature that the monitor advertises and that can be
+    /// written back.
+    pub fn capture(monitor: &Monitor) -> Result<MonitorSnapshot, MonitorError> {
+        let caps = monitor.capabilities()?;
+        let mut vcp = BTreeMap::new();
+
+        for feature in caps.vcp {
+            if SKIPPED_FEATURES.contains(&feature.code) || feature.code >= MANUFACTURER_FEATURES {
+                continue;
+            }
+
+            match monitor.get_vcp(feature.code) {
+                Ok(value) if value.kind == VcpType::SetParameter => {
+                    vcp.insert(format!("0x{:02x}", feature.code), value.current);
+                }
+                Ok(_) => {}
+                Err(e) => log::debug!("skipping vcp feature 0x{:02x}: {}", feature.code, e),
+            }
+        }
+
+        Ok(MonitorSnapshot {
+            name: monitor.name().to_owned(),
+            vcp,
+        })
+    }
+
+    /// Returns the saved values, keyed by VCP code.
+    pub fn values(&self) -> Result<BTreeMap<u8, u32>, Box<dyn Error>> {
+        self.vcp
+            .iter()
+            .map(|(code, value)| Ok((monitors::parse_vcp_code(code)?, *value)))
+            .collect()
+    }
+
+    /// Compares the saved values with the monitor's current ones.
+    pub fn diff(&self, monitor: &Monitor) -> Result<Vec<Difference>, Box<dyn Error>> {
+        let mut differences = vec![];
+
+        for (code, saved) in self.values()? {
+            let current = monitor.get_vcp(code).map(|value| value.current);
+            if current.as_ref() != Ok(&saved) {
+                differences.push(Difference {
+                    code,
+                    saved,
+                    current,
+                });
+            }
+        }
+
+        Ok(differences)
+    }
+}
+
+/// Snapshots are kept as JSON files in the user's data directory.
+fn path(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+
+    if !valid {
+        return Err(format!("invalid snapshot name: {}", name).into());
+    }
+
+    let dir = config::data_dir().ok_or("no data directory to keep snapshots in")?;
+    Ok(dir.join("snapshots").join(format!("{}.json", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::monitors::{DisplayBackend, Handle, MonitorInfo, SimBackend, VcpValue};
+
+    /// Reports the given codes as momentary, which the simulator can't.
+    struct Momentary {
+        sim: SimBackend,
+        codes: Vec<u8>,
+    }
+
+    impl DisplayBackend for Momentary {
+        fn enumerate(&self) -> Result<Vec<MonitorInfo>, MonitorError> {
+            self.sim.enumerate()
+        }
+
+        fn get_vcp(&self, handle: Handle, code: u8) -> Result<VcpValue, MonitorError> {
+            let mut value = self.sim.get_vcp(handle, code)?;
+            if self.codes.contains(&code) {
+                value.kind = VcpType::Momentary;
+            }
+            Ok(value)
+        }
+
+        fn set_vcp(&self, handle: Handle, code: u8, value: u32) -> Result<(), MonitorError> {
+            self.sim.set_vcp(handle, code, value)
+        }
+
+        fn capabilities(&self, handle: Handle) -> Result<String, MonitorError> {
+            self.sim.capabilities(handle)
+        }
+    }
+
+    fn monitor() -> Monitor {
+        // 0x16 is advertised but can't be read, and 0x14 is reported as
+        // momentary.
+        let path = monitors::write_fixture(
+            "snapshot",
+            r#"
+            [[monitors]]
+            id = 1
+            name = "Dell"
+            capabilities = "(prot(monitor)vcp(02 10 12 14 16 60 D6 E0 F1)mccs_ver(2.1))"
+
+            [monitors.vcp]
+            "0x02" = 1
+            "0x10" = 50
+            "0x12" = { current = 75, max = 80 }
+            "0x14" = 5
+            "0x60" = 15
+            "0xe0" = 1
+            "0xf1" = 2
+            "#,
+        );
+        let backend: Arc<dyn DisplayBackend> = Arc::new(Momentary {
+            sim: SimBackend::from_file(path).unwrap(),
+            codes: vec![0x14],
+        });
+        monitors::get_monitors(&backend).unwrap().remove(0)
+    }
+
+    fn snapshot(vcp: &[(&str, u32)]) -> MonitorSnapshot {
+        MonitorSnapshot {
+            name: "Dell".to_owned(),
+            vcp: vcp
+                .iter()
+                .map(|(code, value)| (code.to_string(), *value))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn captures_restorable_features() {
+        let snapshot = MonitorSnapshot::capture(&monitor()).unwrap();
+
+        assert_eq!(snapshot.name, "Dell");
+        assert_eq!(
+            snapshot.vcp.into_iter().collect::<Vec<_>>(),
+            [("0x10".to_owned(), 50), ("0x12".to_owned(), 75)]
+        );
+    }
+
+    #[test]
+    fn diffs_against_current_values() {
+        let monitor = monitor();
+        let saved = snapshot(&[("0x10", 50), ("0x12", 40), ("0x16", 10)]);
+
+        let differences: Vec<_> = saved
+            .diff(&monitor)
+            .unwrap()
+            .into_iter()
+            .map(|d| (d.code, d.saved, d.current))
+            .collect();
+        assert_eq!(
+            differences,
+            [
+                (0x12, 40, Ok(75)),
+                (0x16, 10, Err(MonitorError::UnsupportedFeature(0x16)))
+            ]
+        );
+
+        monitor.set_vcp(0x12, 40).unwrap();
+        assert_eq!(saved.diff(&monitor).unwrap().len(), 1);
+
+        assert!(snapshot(&[("brightness", 50)]).diff(&monitor).is_err());
+    }
+
+    #[test]
+    fn validates_names() {
+        for name in &["", "../x", ".hidden", "a/b", "a\\b", "a b"] {
+            assert!(path(name).is_err(), "{:?}", name);
+        }
+
+        if config::data_dir().is_some() {
+            let path = path("evening.v2").unwrap();
+            assert!(path.ends_with("snapshots/evening.v2.json"));
+        }
+    }
+}